@@ -0,0 +1,36 @@
+-- The initial webpages schema
+--
+-- Databases set up by older versions (by `DB::new` or `postgresql/initdb.sql`) already have
+-- some of this, so everything here is written to be safe to run over them.
+
+CREATE TABLE IF NOT EXISTS webpages (
+    id serial PRIMARY KEY,
+    title TEXT NOT NULL,
+    blurb TEXT,
+    content TEXT NOT NULL,
+    number_js INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    search_vector tsvector,
+    timestamp timestamptz NOT NULL DEFAULT now()
+);
+
+-- initdb.sql never had the timestamp column
+ALTER TABLE webpages ADD COLUMN IF NOT EXISTS timestamp timestamptz NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS ix_search_vector ON webpages USING GIN (search_vector);
+
+CREATE OR REPLACE FUNCTION update_webpage_content() RETURNS trigger AS $$
+BEGIN
+    new.search_vector := setweight(to_tsvector(coalesce(new.title, '')), 'A') ||
+        setweight(to_tsvector(coalesce(new.blurb, '')), 'B') ||
+        setweight(to_tsvector(coalesce(new.content, '')), 'C') ||
+        setweight(to_tsvector(coalesce(new.url, '')), 'D');
+    return new;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS webpage_search_vector_update ON webpages;
+CREATE TRIGGER webpage_search_vector_update
+BEFORE INSERT OR UPDATE
+ON webpages
+FOR EACH ROW EXECUTE PROCEDURE update_webpage_content();
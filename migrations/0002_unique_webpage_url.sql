@@ -0,0 +1,8 @@
+-- Make url unique, so pages can be upserted with `ON CONFLICT (url)`
+
+-- Concurrent crawlers could have added the same page more than once, keep the newest row
+DELETE FROM webpages AS older
+USING webpages AS newer
+WHERE older.url = newer.url AND older.id < newer.id;
+
+ALTER TABLE webpages ADD CONSTRAINT webpages_url_key UNIQUE (url);
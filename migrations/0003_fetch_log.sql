@@ -0,0 +1,25 @@
+-- A log of crawls, and of every fetch attempt made during them
+
+CREATE TABLE IF NOT EXISTS crawls (
+    id bigserial PRIMARY KEY,
+    started_at timestamptz NOT NULL DEFAULT now(),
+    finished_at timestamptz
+);
+
+CREATE TABLE IF NOT EXISTS fetch_log (
+    id bigserial PRIMARY KEY,
+    crawl_id bigint REFERENCES crawls (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    host TEXT NOT NULL,
+    fetched_at timestamptz NOT NULL DEFAULT now(),
+    -- null when no response was received
+    status smallint,
+    bytes integer,
+    latency_ms integer,
+    -- one of ok, robots_denied, filtered, stale, http_error, timeout, not_html, circuit_open, trap, error
+    outcome TEXT NOT NULL,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS ix_fetch_log_crawl_id ON fetch_log (crawl_id);
+CREATE INDEX IF NOT EXISTS ix_fetch_log_fetched_at ON fetch_log (fetched_at);
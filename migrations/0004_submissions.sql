@@ -0,0 +1,18 @@
+-- Sites submitted through the public submission form, waiting for an admin to review them
+
+CREATE TABLE IF NOT EXISTS submissions (
+    id bigserial PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- anything the submitter said about the site
+    note TEXT,
+    -- the address the submission came from, for rate limiting
+    submitter TEXT NOT NULL,
+    submitted_at timestamptz NOT NULL DEFAULT now(),
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+    reviewed_at timestamptz
+);
+
+-- a site can only be waiting for review once
+CREATE UNIQUE INDEX IF NOT EXISTS ux_submissions_pending_url ON submissions (url) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS ix_submissions_submitter ON submissions (submitter, submitted_at);
+CREATE INDEX IF NOT EXISTS ix_submissions_status ON submissions (status, submitted_at);
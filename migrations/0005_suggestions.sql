@@ -0,0 +1,20 @@
+-- Sources for search suggestions (`/api/suggest`)
+
+-- Searches that found something, normalised to lower case, with how often they were made
+CREATE TABLE search_queries (
+    query TEXT PRIMARY KEY,
+    count BIGINT NOT NULL DEFAULT 1,
+    last_searched timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX ix_search_queries_prefix ON search_queries (query text_pattern_ops);
+
+-- Every lexeme in the index with how many pages it's in, refreshed at the end of each crawl
+CREATE MATERIALIZED VIEW search_terms AS
+    SELECT word, ndoc FROM ts_stat('SELECT search_vector FROM webpages');
+
+-- Unique so the view can be refreshed concurrently
+CREATE UNIQUE INDEX ux_search_terms_word ON search_terms (word);
+CREATE INDEX ix_search_terms_prefix ON search_terms (word text_pattern_ops);
+
+CREATE INDEX ix_webpages_title_prefix ON webpages (lower(title) text_pattern_ops);
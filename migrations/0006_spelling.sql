@@ -0,0 +1,14 @@
+-- Spelling corrections ("did you mean") by trigram similarity to the words in the index
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Every word in the index, unstemmed, with how many pages it's in, refreshed at the end of each
+-- crawl along with search_terms
+CREATE MATERIALIZED VIEW search_vocabulary AS
+    SELECT word, ndoc FROM ts_stat($$
+        SELECT to_tsvector('simple', title || ' ' || coalesce(blurb, '') || ' ' || content) FROM webpages
+    $$)
+    WHERE length(word) BETWEEN 2 AND 40;
+
+CREATE UNIQUE INDEX ux_search_vocabulary_word ON search_vocabulary (word);
+CREATE INDEX ix_search_vocabulary_trgm ON search_vocabulary USING GIN (word gin_trgm_ops);
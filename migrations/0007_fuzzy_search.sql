@@ -0,0 +1,5 @@
+-- Trigram indexes for the fuzzy search mode, matching partial names and identifiers in titles
+-- and URLs
+
+CREATE INDEX ix_webpages_title_trgm ON webpages USING GIN (title gin_trgm_ops);
+CREATE INDEX ix_webpages_url_trgm ON webpages USING GIN (url gin_trgm_ops);
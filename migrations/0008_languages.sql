@@ -0,0 +1,19 @@
+-- Index each page with the text search configuration for its language
+
+ALTER TABLE webpages ADD COLUMN lang TEXT;
+
+-- Pages already indexed were indexed with the default configuration, so are kept on it
+ALTER TABLE webpages ADD COLUMN search_config regconfig NOT NULL DEFAULT get_current_ts_config();
+ALTER TABLE webpages ALTER COLUMN search_config SET DEFAULT 'simple';
+
+CREATE INDEX ix_webpages_lang ON webpages (lang);
+
+CREATE OR REPLACE FUNCTION update_webpage_content() RETURNS trigger AS $$
+BEGIN
+    new.search_vector := setweight(to_tsvector(new.search_config, coalesce(new.title, '')), 'A') ||
+        setweight(to_tsvector(new.search_config, coalesce(new.blurb, '')), 'B') ||
+        setweight(to_tsvector(new.search_config, coalesce(new.content, '')), 'C') ||
+        setweight(to_tsvector(new.search_config, coalesce(new.url, '')), 'D');
+    return new;
+END
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,19 @@
+-- Signals for ranking search results, besides how well the text matches
+
+-- The links found to the page in the crawl that indexed it, null where that isn't known (like
+-- imported pages)
+ALTER TABLE webpages ADD COLUMN inbound_links INTEGER;
+
+-- How many path segments a URL has, 0 for a host's home page
+CREATE FUNCTION url_depth(url TEXT) RETURNS INTEGER AS $$
+    SELECT length(path) - length(replace(path, '/', ''))
+    FROM (SELECT rtrim(coalesce(substring(url FROM '^[a-zA-Z][a-zA-Z0-9+.-]*://[^/?#]*([^?#]*)'), ''), '/') AS path) AS parts
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- How fresh something is, from 1 when new, halving every `half_life_days`, or 0 if
+-- `half_life_days` isn't positive
+CREATE FUNCTION freshness(since TIMESTAMPTZ, half_life_days DOUBLE PRECISION) RETURNS DOUBLE PRECISION AS $$
+    SELECT CASE WHEN half_life_days > 0 THEN
+        power(0.5, greatest(extract(epoch FROM now() - since), 0) / 86400 / half_life_days)
+    ELSE 0 END
+$$ LANGUAGE SQL STABLE;
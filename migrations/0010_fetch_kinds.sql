@@ -0,0 +1,5 @@
+-- What each fetch in the log was for: a page, a host's robots.txt or a sitemap, so the requests
+-- made to find out what to crawl aren't counted as pages
+ALTER TABLE fetch_log ADD COLUMN kind TEXT NOT NULL DEFAULT 'page';
+
+UPDATE fetch_log SET kind = 'robots' WHERE url LIKE '%://%/robots.txt';
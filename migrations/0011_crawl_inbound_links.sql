@@ -0,0 +1,4 @@
+-- The links found to each page by the latest crawl to find any, added to in batches as that crawl
+-- goes on, so `inbound_links` can keep the most any one crawl found
+ALTER TABLE webpages ADD COLUMN crawl_inbound_links INTEGER;
+ALTER TABLE webpages ADD COLUMN inbound_links_crawl_id BIGINT REFERENCES crawls (id) ON DELETE SET NULL;
@@ -1,88 +1,586 @@
 use axum::{
-    extract::{self, State},
-    response::IntoResponse,
-    routing::get,
-    http::{header, StatusCode},
+    body::Body,
+    extract::{self, ConnectInfo, Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response, sse::{KeepAlive, Sse}},
+    routing::{get, post},
+    http::{header, HeaderMap, StatusCode},
     Json, Router,
 };
 use tower_http::compression::CompressionLayer;
-use log::info;
+use log::{info, warn, error};
 use std::sync::Arc;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::watch;
+use futures_util::StreamExt;
 
-use crate::db::{DB, SearchResult};
+use crate::config::ServerConfig;
+use crate::db::{DB, SearchResponse, Submission, SubmissionStatus, Upsert};
+use crate::error::{CrawlError, JobError, QueryError, SetupError, SubmitError};
+use crate::jobs::{CrawlJobs, JobInfo, JobOptions};
+use crate::progress;
+use crate::submissions;
 
 /// State struct to hold the database for the axum server
 struct AppState {
-    db: DB
+    db: DB,
+    /// The normalised base path, either empty or starting with a `/` with no trailing `/`
+    base_path: String,
+    /// The URL the server is reached at publicly, with no trailing `/` (see `public_url`)
+    public_url: String,
+    /// The progress endpoint of a running crawl, with no trailing `/`
+    crawl_progress_url: Option<String>,
+    /// The client used to reach the crawl progress endpoint
+    client: reqwest::Client,
+    /// The token the admin API is protected by, if it's enabled
+    admin_token: Option<String>,
+    /// The crawl jobs started through the admin API
+    jobs: CrawlJobs,
+    /// How many sites one address can submit an hour, with 0 meaning submissions are off
+    submissions_per_hour: u32,
+    /// Whether to take client addresses from `X-Forwarded-For`
+    trust_proxy_headers: bool,
+    /// Turns true when the server is shutting down, to end streamed responses
+    shutdown: watch::Receiver<bool>,
 }
 
-/// Start the server with a DB connection
-pub async fn serve(db: DB) {
+/// Start the server with a DB connection and the runner for crawl jobs, until `shutdown` turns
+/// true
+///
+/// On shutdown no more connections are accepted, any crawl job is stopped and progress streams
+/// are ended, and the server waits for the requests in flight to finish before closing the
+/// database.
+pub async fn serve(db: DB, config: ServerConfig, jobs: CrawlJobs, shutdown: watch::Receiver<bool>) {
     info!("Starting server...");
-    
-    let shared_state = Arc::new(AppState{db});
 
-    let app = Router::new()
+    let base_path = normalise_base_path(&config.base_path);
+    let shared_state = Arc::new(AppState{
+        jobs,
+        db,
+        base_path: base_path.clone(),
+        public_url: public_url(&config, &base_path),
+        crawl_progress_url: config.crawl_progress_url.map(|url| url.trim_end_matches('/').to_string()),
+        client: reqwest::Client::new(),
+        admin_token: config.admin_token.filter(|token| !token.is_empty()),
+        submissions_per_hour: config.submissions_per_hour,
+        trust_proxy_headers: config.trust_proxy_headers,
+        shutdown: shutdown.clone(),
+    });
+
+    let admin_routes = Router::new()
+        .route("/seeds", post(admin_enqueue))
+        .route("/jobs", get(admin_list_jobs).post(admin_start_job))
+        .route("/jobs/:id", get(admin_get_job))
+        .route("/jobs/:id/pause", post(admin_pause_job))
+        .route("/jobs/:id/resume", post(admin_resume_job))
+        .route("/jobs/:id/cancel", post(admin_cancel_job))
+        .route("/pages", axum::routing::delete(admin_delete_page))
+        .route("/pages/reindex", post(admin_reindex_page))
+        .route("/submissions", get(admin_list_submissions))
+        .route("/submissions/:id/approve", post(admin_approve_submission))
+        .route("/submissions/:id/reject", post(admin_reject_submission))
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), require_admin));
+
+    let routes = Router::new()
         .route("/", get(homepage))
         .route("/about", get(about))
+        .route("/submit", get(submit_page).post(submit_site))
         .route("/ping", get(pong))
+        .route("/opensearch.xml", get(opensearch))
         .route("/search", get(search))
         .route("/api/search", get(api_search))
+        .route("/api/suggest", get(api_suggest))
+        .route("/crawl", get(crawl_dashboard))
+        .route("/api/crawl/progress", get(crawl_progress))
+        .route("/api/crawl/events", get(crawl_events))
+        .nest("/api/admin", admin_routes);
+
+    let app = if base_path.is_empty() {
+        routes
+    } else {
+        Router::new()
+            .route(&format!("{base_path}/"), get(homepage))
+            .nest(&base_path, routes)
+    };
+    let app = app
         .layer(CompressionLayer::new())
         .fallback(handler_404)
-        .with_state(shared_state);
+        .with_state(shared_state.clone());
+
+    if config.unix_socket.is_some() && config.submissions_per_hour > 0 && !config.trust_proxy_headers {
+        warn!("Without trust_proxy_headers every submission over the unix socket counts against one shared limit");
+    }
+    let server = async {
+        match config.unix_socket {
+            Some(path) => serve_unix(app, path, shutdown.clone()).await,
+            None => {
+                let addr = format!("{}:{}", config.bind, config.port);
+                let listener = tokio::net::TcpListener::bind(&addr).await
+                    .unwrap_or_else(|e| panic!("Failed to bind to {addr}: {e}"));
+                info!("Listening on http://{addr}{base_path}/");
+                axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(crate::shutdown::requested(shutdown.clone()))
+                    .await.unwrap();
+            }
+        }
+    };
+    // Jobs are stopped while the connections drain, not after, so they don't keep crawling
+    let stop_jobs = async {
+        crate::shutdown::requested(shutdown.clone()).await;
+        if shared_state.jobs.active() { info!("Stopping crawl job...") }
+        shared_state.jobs.shutdown().await;
+    };
+    tokio::join!(server, stop_jobs);
+
+    info!("Server stopped, closing database...");
+    shared_state.db.close().await;
+}
+
+/// Serve the app over a unix domain socket, replacing any stale socket file, until `shutdown`
+/// turns true and the open connections have finished
+///
+/// Exits if something other than a socket is at `path`, rather than deleting it.
+#[cfg(unix)]
+async fn serve_unix(app: Router, path: PathBuf, shutdown: watch::Receiver<bool>) {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::task::JoinSet;
+
+    match std::fs::symlink_metadata(&path) {
+        Ok(meta) if meta.file_type().is_socket() => { let _ = std::fs::remove_file(&path); },
+        Ok(_) => {
+            eprintln!("Not listening on {}, it already exists and isn't a socket", path.display());
+            std::process::exit(1)
+        },
+        Err(_) => {},
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .unwrap_or_else(|e| panic!("Failed to bind to {}: {e}", path.display()));
+    info!("Listening on unix:{}", path.display());
+
+    let mut connections = JoinSet::new();
+    loop {
+        let (socket, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(conn) => conn,
+                Err(e) => { error!("Failed to accept connection: {e}"); continue }
+            },
+            _ = crate::shutdown::requested(shutdown.clone()) => break,
+        };
+        let service = TowerToHyperService::new(app.clone());
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let builder = Builder::new(TokioExecutor::new());
+            let conn = builder.serve_connection_with_upgrades(TokioIo::new(socket), service);
+            tokio::pin!(conn);
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = crate::shutdown::requested(shutdown) => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result { error!("Connection error: {e}") }
+        });
+        // Forget about connections that have finished
+        while connections.try_join_next().is_some() {}
+    }
 
-    // TODO: customisable port
+    info!("Waiting for {} connections to finish...", connections.len());
+    while connections.join_next().await.is_some() {}
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_app: Router, _path: PathBuf, _shutdown: watch::Receiver<bool>) {
+    panic!("Unix domain sockets are not supported on this platform!");
+}
+
+/// Turn a user-supplied base path like `search-engine/` into `/search-engine`, or `""` for the
+/// root
+fn normalise_base_path(base_path: &str) -> String {
+    let trimmed = base_path.trim_matches('/');
+    if trimmed.is_empty() { String::new() } else { format!("/{trimmed}") }
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:22001").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// The URL the server is reached at publicly, from the config, or else the address it listens on
+///
+/// It's never taken from a request, as the `Host` header can be anything the client likes.
+fn public_url(config: &ServerConfig, base_path: &str) -> String {
+    if let Some(url) = config.public_url.as_deref().map(|url| url.trim_end_matches('/')).filter(|url| !url.is_empty()) {
+        return url.to_string();
+    }
+    let url = match (&config.unix_socket, config.bind.as_str()) {
+        (Some(_), _) | (None, "" | "0.0.0.0" | "::") => format!("http://localhost:{}{base_path}", config.port),
+        (None, bind) if bind.contains(':') => format!("http://[{bind}]:{}{base_path}", config.port),
+        (None, bind) => format!("http://{bind}:{}{base_path}", config.port),
+    };
+    warn!("public_url isn't set, so the OpenSearch description points to {url}");
+    url
 }
 
 /// Returns the home page response
-async fn homepage() -> impl IntoResponse{
+async fn homepage(State(state): State<Arc<AppState>>) -> impl IntoResponse{
     (StatusCode::OK, [
             (header::CONTENT_TYPE, "text/html; charset=utf-8")
         ], 
-        process_template(include_str!("html/index.html")))
+        process_template(include_str!("html/index.html"), &state))
 }
 
 /// Returns the about page reponse
-async fn about() -> impl IntoResponse{
+async fn about(State(state): State<Arc<AppState>>) -> impl IntoResponse{
     (StatusCode::OK, [
             (header::CONTENT_TYPE, "text/html; charset=utf-8")
         ], 
-        process_template(include_str!("html/about.html")))
+        process_template(include_str!("html/about.html"), &state))
+}
+
+/// Returns the OpenSearch description, so browsers can add the search engine and its suggestions
+async fn opensearch(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (StatusCode::OK, [
+            (header::CONTENT_TYPE, "application/opensearchdescription+xml; charset=utf-8")
+        ],
+        include_str!("html/opensearch.xml").replace("{base_url}", &escape_html(&state.public_url)))
+}
+
+/// Returns the site submission form
+async fn submit_page(State(state): State<Arc<AppState>>) -> Response {
+    if state.submissions_per_hour == 0 { return handler_404().await.into_response() }
+    submit_response(&state, StatusCode::OK, "")
+}
+
+/// A site sent through the submission form
+#[derive(Deserialize)]
+struct SubmitForm {
+    url: String,
+    note: Option<String>,
+}
+
+/// Add a site from the submission form to the moderation queue
+async fn submit_site(State(state): State<Arc<AppState>>, connect_info: Option<ConnectInfo<SocketAddr>>, headers: HeaderMap,
+                     extract::Form(form): extract::Form<SubmitForm>) -> Response {
+    if state.submissions_per_hour == 0 { return handler_404().await.into_response() }
+    let submitter = client_address(&state, connect_info, &headers);
+    let result = submissions::submit(&state.db, state.jobs.rules(), state.submissions_per_hour,
+                                     &form.url, form.note.as_deref(), &submitter).await;
+    match result {
+        Ok(id) => {
+            info!("Site {} submitted from {submitter} as {id}", form.url.trim());
+            submit_response(&state, StatusCode::OK, "Thanks! The site will be crawled once it's been reviewed.")
+        },
+        Err(e) => {
+            let status = match &e {
+                SubmitError::Invalid(_) => StatusCode::BAD_REQUEST,
+                SubmitError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+                SubmitError::Duplicate => StatusCode::CONFLICT,
+                SubmitError::Storage(_) => {
+                    let err = ApiError::internal("Submitting a site", &e);
+                    return submit_response(&state, err.status, "Sorry, something went wrong, please try again later.");
+                },
+            };
+            submit_response(&state, status, &e.to_string())
+        }
+    }
+}
+
+/// The submission form with a message above it
+fn submit_response(state: &AppState, status: StatusCode, message: &str) -> Response {
+    let message = if message.is_empty() { String::new() } else { format!("<p><b>{}</b></p>", escape_html(message)) };
+    let page = process_template(include_str!("html/submit.html"), state).replace("<!--message-->", &message);
+    (status, [(header::CONTENT_TYPE, "text/html; charset=utf-8")], page).into_response()
+}
+
+/// The address a request came from, which is `local` over a unix socket
+///
+/// Without `trust_proxy_headers` every request over a unix socket has the same address, so
+/// they share one submission limit.
+fn client_address(state: &AppState, connect_info: Option<ConnectInfo<SocketAddr>>, headers: &HeaderMap) -> String {
+    let forwarded = headers.get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .filter(|v| state.trust_proxy_headers && !v.is_empty());
+    match (forwarded, connect_info) {
+        (Some(addr), _) => addr.to_string(),
+        (None, Some(ConnectInfo(addr))) => addr.ip().to_string(),
+        (None, None) => String::from("local"),
+    }
 }
 
 /// Returns the search results response
 async fn search(State(state): State<Arc<AppState>>, extract::Query(query): extract::Query<HashMap<String, String>>) -> impl IntoResponse {
     
-    let mut default_page: String = process_template(include_str!("html/main.html"));
-    if query.contains_key("q") {
-        default_page = default_page.replace("name=\"q\"", format!("name=\"q\" value=\"{}\"", &query.get("q").unwrap()).as_str());
+    let mut status = StatusCode::OK;
+    let mut default_page: String = process_template(include_str!("html/main.html"), &state);
+    if let Some(q) = query.get("q") {
+        default_page = default_page.replace("name=\"q\"", format!("name=\"q\" value=\"{}\"", escape_html(q)).as_str());
 
         //TODO check for bangs
         
         let mut search_items = String::new();
-        for res in state.db.search(query.get("q").unwrap().as_str()).await.unwrap() {
-        search_items += format!(r#"
-            <a href="{1}"><b>{0}</b>
-            <i>{1}</i><br>
-            {2}</a><br><br>
-            "#, res.title, res.url, res.blurb.unwrap_or(String::new())).as_str();
+        let mode = query.get("mode").map(String::as_str).unwrap_or_default();
+        let response = match mode.parse() {
+            Ok(mode) => state.db.search_with_suggestion(q, mode).await,
+            Err(e) => Err(e),
+        };
+        match response {
+            Ok(response) => {
+                let response = log_query(&state, response).await;
+                if let Some(suggestion) = &response.suggestion {
+                    let mut encoded: String = url::form_urlencoded::byte_serialize(suggestion.as_bytes()).collect();
+                    if !mode.is_empty() {
+                        encoded += "&amp;mode=";
+                        encoded.extend(url::form_urlencoded::byte_serialize(mode.as_bytes()));
+                    }
+                    search_items += format!(r#"<p>Did you mean <a href="{}/search?q={encoded}"><b>{}</b></a>?</p>
+            "#, state.base_path, escape_html(suggestion)).as_str();
+                }
+                // Everything about a result comes from the page crawled, so it's all escaped, and
+                // only web addresses are linked to
+                for res in response.results {
+                    let href = if res.url.starts_with("http://") || res.url.starts_with("https://") { escape_html(&res.url) } else { String::new() };
+                    search_items += format!(r#"
+            <a href="{href}"><b>{}</b>
+            <i>{}</i><br>
+            {}</a><br><br>
+            "#, escape_html(&res.title), escape_html(&res.url), escape_html(res.blurb.as_deref().unwrap_or_default())).as_str();
+                }
+            },
+            Err(e) => {
+                let err = ApiError::from(e);
+                status = err.status;
+                search_items = format!("<p>{}</p>", escape_html(&err.message));
+            }
         }
         default_page = default_page.replace("<!---->", search_items.as_str());
     }
-    (StatusCode::OK, [(header::CONTENT_TYPE, "text/html; charset=utf-8")], default_page)
+    (status, [(header::CONTENT_TYPE, "text/html; charset=utf-8")], default_page)
 }
 
 /// API endpoint for search results
-async fn api_search(State(state): State<Arc<AppState>>, extract::Query(query): extract::Query<HashMap<String, String>>) -> Json<Vec<SearchResult>> {
-    if query.contains_key("q") {
-        Json(state.db.search(query.get("q").unwrap().as_str()).await.unwrap())
+///
+/// Takes the query as `q` and optionally a `mode` of `standard`, `fuzzy` or `auto`, and responds
+/// with `{"query": ..., "results": [...], "suggestion": ...}`, where `suggestion` is a spelling
+/// correction when there were few results, or null.
+async fn api_search(State(state): State<Arc<AppState>>, extract::Query(query): extract::Query<HashMap<String, String>>) -> Result<Json<SearchResponse>, ApiError> {
+    let q = query.get("q").map(String::as_str).unwrap_or_default();
+    let mode = query.get("mode").map(String::as_str).unwrap_or_default().parse()?;
+    let debug = matches!(query.get("debug").map(String::as_str), Some("1" | "true"));
+    let mut response = state.db.search_with_suggestion(q, mode).await?;
+    if debug {
+        response.debug = Some(state.db.explain_query(q, response.mode).await?);
     } else {
-        Json(Vec::<SearchResult>::new())
+        for result in &mut response.results { result.score = None }
+    }
+    Ok(Json(log_query(&state, response).await))
+}
+
+/// Count a search towards the suggestions if it found something, passing it on
+async fn log_query(state: &AppState, response: SearchResponse) -> SearchResponse {
+    if !response.results.is_empty() {
+        if let Err(e) = state.db.log_query(&response.query).await { warn!("Couldn't log search: {e}") }
+    }
+    response
+}
+
+/// Completions for a partly typed search, as OpenSearch suggestions: `[query, [completions]]`
+async fn api_suggest(State(state): State<Arc<AppState>>, extract::Query(query): extract::Query<HashMap<String, String>>) -> Result<Response, ApiError> {
+    let q = query.get("q").map(String::as_str).unwrap_or_default();
+    let suggestions = state.db.suggest(q).await.map_err(|e| ApiError::internal("Suggesting", e))?;
+    Ok(([(header::CONTENT_TYPE, "application/x-suggestions+json")], Json(json!([q, suggestions]))).into_response())
+}
+
+/// Returns the live crawl dashboard page
+async fn crawl_dashboard(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (StatusCode::OK, [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8")
+        ],
+        process_template(include_str!("html/crawl.html"), &state))
+}
+
+/// The progress of the running crawl
+///
+/// This is the crawl job running in the server if there is one, or else the crawl at the
+/// configured progress endpoint, or else the last job that ran.
+async fn crawl_progress(State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+    match local_progress(&state) {
+        Some(progress) => Ok(Json(progress.borrow().clone()).into_response()),
+        None => forward_progress(&state, "/progress").await,
+    }
+}
+
+/// Server-sent events with the progress of the running crawl, from the same place as
+/// `crawl_progress`
+async fn crawl_events(State(state): State<Arc<AppState>>) -> Result<Response, ApiError> {
+    match local_progress(&state) {
+        Some(progress) => Ok(Sse::new(progress::updates(progress, state.shutdown.clone())).keep_alive(KeepAlive::default()).into_response()),
+        None => forward_progress(&state, "/progress/events").await,
+    }
+}
+
+/// The progress of a crawl job in the server, if it should be shown over the progress endpoint
+fn local_progress(state: &AppState) -> Option<watch::Receiver<progress::CrawlProgress>> {
+    if state.jobs.active() || state.crawl_progress_url.is_none() { state.jobs.progress() } else { None }
+}
+
+/// Pass on a response from the crawl progress endpoint, streaming its body
+async fn forward_progress(state: &AppState, path: &str) -> Result<Response, ApiError> {
+    let base = state.crawl_progress_url.as_ref().ok_or_else(|| ApiError {
+        status: StatusCode::NOT_FOUND,
+        message: String::from("no crawl progress endpoint is configured"),
+    })?;
+    let resp = state.client.get(format!("{base}{path}")).send().await.map_err(|e| {
+        info!("Couldn't reach crawl progress endpoint: {e}");
+        ApiError { status: StatusCode::BAD_GATEWAY, message: String::from("no crawl is running") }
+    })?;
+    let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
+    let status = StatusCode::from_u16(resp.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    // Streams are cut off on shutdown, rather than holding it up for as long as the crawl runs
+    let body = resp.bytes_stream().take_until(crate::shutdown::requested(state.shutdown.clone()));
+    Ok((status, [(header::CONTENT_TYPE, content_type)], Body::from_stream(body)).into_response())
+}
+
+/// Reject admin API requests without the admin token, as `Authorization: Bearer <token>`
+async fn require_admin(State(state): State<Arc<AppState>>, headers: HeaderMap, request: Request, next: Next) -> Response {
+    let Some(token) = &state.admin_token else {
+        return ApiError { status: StatusCode::NOT_FOUND, message: String::from("the admin API is disabled") }.into_response();
+    };
+    let given = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq(given.as_bytes(), token.as_bytes()) {
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")],
+                Json(json!({ "error": "a valid admin token is required" }))).into_response();
+    }
+    next.run(request).await
+}
+
+/// Compare two secrets without leaking where they differ through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Seeds to enqueue through the admin API
+#[derive(Deserialize)]
+struct EnqueueSeeds {
+    seeds: Vec<String>,
+}
+
+/// Enqueue seeds for the next crawl job
+async fn admin_enqueue(State(state): State<Arc<AppState>>, Json(body): Json<EnqueueSeeds>) -> Result<Json<serde_json::Value>, ApiError> {
+    let pending = state.jobs.enqueue(&body.seeds)?;
+    Ok(Json(json!({ "pending": pending })))
+}
+
+/// List every crawl job
+async fn admin_list_jobs(State(state): State<Arc<AppState>>) -> Json<Vec<JobInfo>> {
+    Json(state.jobs.list())
+}
+
+/// Start a crawl job
+async fn admin_start_job(State(state): State<Arc<AppState>>, options: Option<Json<JobOptions>>) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    let options = options.map(|Json(options)| options).unwrap_or_default();
+    Ok((StatusCode::CREATED, Json(state.jobs.start(options)?)))
+}
+
+/// Show a single crawl job
+async fn admin_get_job(State(state): State<Arc<AppState>>, extract::Path(id): extract::Path<u64>) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.get(id)?))
+}
+
+/// Pause a crawl job
+async fn admin_pause_job(State(state): State<Arc<AppState>>, extract::Path(id): extract::Path<u64>) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.pause(id)?))
+}
+
+/// Carry on with a paused crawl job
+async fn admin_resume_job(State(state): State<Arc<AppState>>, extract::Path(id): extract::Path<u64>) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.resume(id)?))
+}
+
+/// Cancel a crawl job
+async fn admin_cancel_job(State(state): State<Arc<AppState>>, extract::Path(id): extract::Path<u64>) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.cancel(id)?))
+}
+
+/// A URL given to the admin API
+#[derive(Deserialize)]
+struct PageUrl {
+    url: String,
+}
+
+/// Remove a URL from the index
+async fn admin_delete_page(State(state): State<Arc<AppState>>, extract::Query(page): extract::Query<PageUrl>) -> Result<Json<serde_json::Value>, ApiError> {
+    match state.db.delete_webpage(&page.url).await {
+        Ok(true) => {
+            refresh_terms(&state).await;
+            Ok(Json(json!({ "deleted": page.url })))
+        },
+        Ok(false) => Err(ApiError { status: StatusCode::NOT_FOUND, message: format!("{} isn't in the index", page.url) }),
+        Err(e) => Err(ApiError::internal("Deleting a page", e)),
+    }
+}
+
+/// Fetch and index a URL now, however recently it was indexed
+async fn admin_reindex_page(State(state): State<Arc<AppState>>, Json(page): Json<PageUrl>) -> Result<Json<serde_json::Value>, ApiError> {
+    let result = match state.jobs.reindex(&page.url).await? {
+        Upsert::Inserted(_) => "inserted",
+        Upsert::Updated(_) => "updated",
+        Upsert::Unchanged(_) => "unchanged",
+    };
+    if result != "unchanged" { refresh_terms(&state).await }
+    Ok(Json(json!({ "url": page.url, "result": result })))
+}
+
+/// Rebuild the suggestion and spelling dictionaries after the indexed text has changed, only
+/// warning if that fails
+async fn refresh_terms(state: &AppState) {
+    if let Err(e) = state.db.refresh_terms().await { warn!("Couldn't refresh the suggestion and spelling dictionaries: {e}") }
+}
+
+/// Which submissions to list through the admin API
+#[derive(Deserialize)]
+struct SubmissionFilter {
+    status: Option<SubmissionStatus>,
+    limit: Option<i64>,
+}
+
+/// List submitted sites, oldest first
+async fn admin_list_submissions(State(state): State<Arc<AppState>>, extract::Query(filter): extract::Query<SubmissionFilter>) -> Result<Json<Vec<Submission>>, ApiError> {
+    let limit = filter.limit.unwrap_or(100).clamp(1, 1000);
+    state.db.submissions(filter.status, limit).await
+        .map(Json)
+        .map_err(|e| ApiError::internal("Listing submissions", e))
+}
+
+/// Approve a pending submission, adding the site to the crawl frontier
+async fn admin_approve_submission(State(state): State<Arc<AppState>>, extract::Path(id): extract::Path<i64>) -> Result<Json<serde_json::Value>, ApiError> {
+    let submission = review_submission(&state, id, SubmissionStatus::Approved).await?;
+    let pending = state.jobs.enqueue(std::slice::from_ref(&submission.url))?;
+    Ok(Json(json!({ "submission": submission, "pending": pending })))
+}
+
+/// Reject a pending submission
+async fn admin_reject_submission(State(state): State<Arc<AppState>>, extract::Path(id): extract::Path<i64>) -> Result<Json<Submission>, ApiError> {
+    Ok(Json(review_submission(&state, id, SubmissionStatus::Rejected).await?))
+}
+
+/// Approve or reject a submission, failing if it isn't pending
+async fn review_submission(state: &AppState, id: i64, status: SubmissionStatus) -> Result<Submission, ApiError> {
+    match state.db.review_submission(id, status).await {
+        Ok(Some(submission)) => Ok(submission),
+        Ok(None) => Err(ApiError { status: StatusCode::NOT_FOUND, message: format!("there is no pending submission {id}") }),
+        Err(e) => Err(ApiError::internal("Reviewing a submission", e)),
     }
 }
 
@@ -96,8 +594,95 @@ async fn handler_404() -> impl IntoResponse {
     (StatusCode::NOT_FOUND, "404: nothing to see here")
 }
 
-fn process_template(template: &str) -> String {
+/// An error returned from the API as JSON, in the form `{"error": "message"}`
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl From<QueryError> for ApiError {
+    fn from(err: QueryError) -> ApiError {
+        match err {
+            QueryError::Empty | QueryError::TooLong { .. } | QueryError::UnknownMode { .. } | QueryError::UnknownLanguage { .. } => ApiError {
+                status: StatusCode::BAD_REQUEST,
+                message: err.to_string(),
+            },
+            QueryError::Storage(e) => ApiError::internal("Search", e),
+        }
+    }
+}
+
+impl From<JobError> for ApiError {
+    fn from(err: JobError) -> ApiError {
+        let status = match &err {
+            JobError::NotFound { .. } => StatusCode::NOT_FOUND,
+            JobError::AlreadyRunning { .. } | JobError::WrongStatus { .. } => StatusCode::CONFLICT,
+            JobError::Setup(SetupError::Client(e)) => return ApiError::internal("Creating the crawler", e),
+            JobError::NoSeeds | JobError::Seed(_) | JobError::Setup(_) => StatusCode::BAD_REQUEST,
+            JobError::Crawl(CrawlError::Storage(e)) => return ApiError::internal("Reindexing", e),
+            JobError::Crawl(_) => StatusCode::BAD_GATEWAY,
+        };
+        ApiError { status, message: err.to_string() }
+    }
+}
+
+impl ApiError {
+    /// An error the client can't do anything about, logged with what was being done and
+    /// reported as just "internal server error"
+    fn internal(action: &str, err: impl std::fmt::Display) -> ApiError {
+        error!("{action} failed: {err}");
+        ApiError { status: StatusCode::INTERNAL_SERVER_ERROR, message: String::from("internal server error") }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+/// Escape text for putting into HTML, either as content or in a quoted attribute
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+/// Fill in the shared parts of a page template, and prefix its local links with the base path
+///
+/// The footer only links to the submission form when submissions are on.
+fn process_template(template: &str, state: &AppState) -> String {
+    let base_path = &state.base_path;
+    let submit_link = if state.submissions_per_hour > 0 { r#" <a href="/submit">Submit a site</a>"# } else { "" };
     let template = template.to_string();
     template.replace("<!--footer-->", include_str!("html/footer.html"))
+            .replace("<!--submit-->", submit_link)
+            .replace("<!--suggest-->", include_str!("html/suggest.html"))
             .replace("/*style*/>",  include_str!("html/style.css"))
+            .replace("href=\"/", format!("href=\"{base_path}/").as_str())
+            .replace("action=\"/", format!("action=\"{base_path}/").as_str())
+            .replace("fetch(\"/", format!("fetch(\"{base_path}/").as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_url_comes_from_the_config() {
+        let config = |public_url: Option<&str>, bind: &str, unix_socket: Option<&str>| ServerConfig {
+            public_url: public_url.map(String::from),
+            bind: bind.to_string(),
+            port: 8080,
+            unix_socket: unix_socket.map(PathBuf::from),
+            ..Default::default()
+        };
+        assert_eq!(public_url(&config(Some("https://example.com/search/"), "0.0.0.0", None), "/search"), "https://example.com/search");
+        assert_eq!(public_url(&config(Some(""), "127.0.0.1", None), ""), "http://127.0.0.1:8080");
+        assert_eq!(public_url(&config(None, "0.0.0.0", None), "/search"), "http://localhost:8080/search");
+        assert_eq!(public_url(&config(None, "::1", None), ""), "http://[::1]:8080");
+        assert_eq!(public_url(&config(None, "10.0.0.2", Some("/run/hunter.sock")), ""), "http://localhost:8080");
+    }
 }
@@ -0,0 +1,156 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+
+/// RetryPolicy - how many times, and how long apart, transient failures are retried
+#[derive(Clone,Debug)]
+pub struct RetryPolicy {
+    /// The max amount of retries after the first attempt
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    /// The longest delay between retries, including any asked for with `Retry-After`
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry` (starting at 1)
+    ///
+    /// This is exponential, with jitter between half and all of it so that retries to the same
+    /// host don't line up. A `Retry-After` from the server is used instead if it's longer.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        let exp = self.base_delay.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let cap = exp.min(self.max_delay);
+        let jittered = cap.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        match retry_after {
+            Some(after) => jittered.max(after).min(self.max_delay),
+            None => jittered,
+        }
+    }
+}
+
+/// The state of the circuit for a single host
+#[derive(Clone,Debug,Default)]
+struct HostCircuit {
+    /// The amount of failures in a row
+    failures: u32,
+    /// When the circuit was opened, requests to the host are stopped until this time
+    open_until: Option<Instant>,
+}
+
+/// CircuitBreaker - stops requests to hosts after repeated failures
+///
+/// After `threshold` failures in a row a host's circuit opens, and no requests are made to it for
+/// the cooldown. After that one request is let through: if it succeeds the circuit closes again,
+/// if it fails the circuit reopens.
+#[derive(Clone,Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    hosts: HashMap<String, HostCircuit>,
+}
+
+impl CircuitBreaker {
+    /// Create a CircuitBreaker opening after `threshold` failures, for `cooldown`
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker { threshold, cooldown, hosts: HashMap::new() }
+    }
+
+    /// If the host's circuit is open, the time it can be tried again
+    pub fn open_until(&self, host: &str) -> Option<Instant> {
+        self.hosts.get(host)
+            .and_then(|c| c.open_until)
+            .filter(|until| *until > Instant::now())
+    }
+
+    /// Record a successful request to a host, closing its circuit
+    pub fn record_success(&mut self, host: &str) {
+        if let Some(circuit) = self.hosts.get_mut(host) {
+            if circuit.open_until.is_some() { info!("Circuit for {host} closed") }
+            *circuit = HostCircuit::default();
+        }
+    }
+
+    /// Record a failed request to a host, opening its circuit if it has failed too many times
+    pub fn record_failure(&mut self, host: &str) {
+        let circuit = self.hosts.entry(host.to_string()).or_default();
+        circuit.failures += 1;
+        if circuit.failures >= self.threshold {
+            warn!("{host} failed {} times in a row, pausing requests to it for {:?}", circuit.failures, self.cooldown);
+            circuit.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { max_retries: 5, base_delay: Duration::from_millis(100), max_delay: Duration::from_millis(1000) }
+    }
+
+    #[test]
+    fn delays_double_with_jitter() {
+        let policy = policy();
+        for (retry, full) in [(1, 100), (2, 200), (3, 400), (4, 800)] {
+            for _ in 0..20 {
+                let delay = policy.delay(retry, None);
+                assert!(delay >= Duration::from_millis(full / 2) && delay <= Duration::from_millis(full), "retry {retry} waited {delay:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn delays_are_capped() {
+        let policy = policy();
+        for retry in [5, 10, 40, u32::MAX] {
+            let delay = policy.delay(retry, None);
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1000), "retry {retry} waited {delay:?}");
+        }
+    }
+
+    #[test]
+    fn longer_retry_after_is_used() {
+        let policy = policy();
+        assert_eq!(policy.delay(1, Some(Duration::from_millis(700))), Duration::from_millis(700));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(60))), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn shorter_retry_after_is_ignored() {
+        let delay = policy().delay(3, Some(Duration::from_millis(1)));
+        assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn circuit_opens_after_threshold() {
+        let mut breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure("example.com");
+        assert!(breaker.open_until("example.com").is_none());
+        breaker.record_failure("example.com");
+        assert!(breaker.open_until("example.com").is_some());
+        assert!(breaker.open_until("example.org").is_none());
+    }
+
+    #[test]
+    fn success_closes_circuit() {
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure("example.com");
+        breaker.record_success("example.com");
+        assert!(breaker.open_until("example.com").is_none());
+        breaker.record_failure("example.com");
+        assert!(breaker.open_until("example.com").is_some());
+    }
+}
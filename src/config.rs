@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use log::{debug, info};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The config file looked for in the working directory when none is given
+pub const DEFAULT_CONFIG_FILE: &str = "hunter-searcher.toml";
+
+/// Config - the full configuration, as read from `hunter-searcher.toml`
+///
+/// Settings are layered: defaults, then the config file, then environment variables, then
+/// command line flags.
+#[derive(Clone,Debug,Default,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    pub crawler: CrawlerConfig,
+    pub server: ServerConfig,
+    pub ranking: RankingConfig,
+}
+
+/// Settings for connecting to postgres
+#[derive(Clone,Debug,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// The username to connect as (`POSTGRES_USER`)
+    pub user: String,
+    /// The password to connect with (`POSTGRES_PASSWORD`)
+    pub password: String,
+    /// The host, including the port (`POSTGRES_HOST`)
+    pub host: String,
+    /// The database name (`POSTGRES_DB`)
+    pub name: String,
+    /// The max amount of connections in the pool
+    pub pool_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            user: String::from("crawler"),
+            password: String::new(),
+            host: String::from("localhost:5432"),
+            name: String::from("index"),
+            pool_size: 5,
+        }
+    }
+}
+
+/// Settings for the crawler
+#[derive(Clone,Debug,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlerConfig {
+    /// The user agent sent with every request, and checked against `robots.txt`
+    pub user_agent: String,
+    /// The minimum time between requests, in milliseconds
+    pub delay_ms: u64,
+    /// How long to wait for a response before giving up, in seconds
+    pub timeout_secs: u64,
+    /// The max amount of times a timed out or failed (429 or 5xx) request is retried
+    pub max_retries: u32,
+    /// The delay before the first retry, doubling for each one after, in milliseconds
+    pub retry_base_ms: u64,
+    /// The longest delay between retries, in milliseconds
+    pub retry_max_ms: u64,
+    /// The amount of failures in a row before requests to a host are paused
+    pub breaker_threshold: u32,
+    /// How long requests to a failing host are paused for, in seconds
+    pub breaker_cooldown_secs: u64,
+    /// How long before an indexed page is considered stale and recrawled, in seconds
+    pub stale_secs: i64,
+    /// The max amount of pages to crawl (-1 for no limit)
+    pub max_pages: i32,
+    /// The max amount of links to follow from a seed
+    pub max_hops: Option<u32>,
+    /// The max amount of pages to crawl from any one host
+    pub max_pages_per_host: Option<usize>,
+    /// The max amount of bytes to download
+    pub max_bytes: Option<u64>,
+    /// The longest URL crawled, longer ones are quarantined as traps (0 for no limit)
+    pub trap_max_url_length: usize,
+    /// The most times a path segment can repeat in a URL before it's quarantined (0 for no limit)
+    pub trap_max_segment_repeats: usize,
+    /// The most URLs with the same path pattern (ignoring digits and query values) to queue
+    /// before quarantining the rest (0 for no limit)
+    pub trap_max_pattern_urls: usize,
+    /// The most URLs to queue from any one host before quarantining the rest (0 for no limit)
+    pub trap_max_host_urls: usize,
+    /// If set, remember the URLs seen in a Bloom filter in this file instead of in memory, for
+    /// crawls too big to keep every URL in memory
+    pub bloom_file: Option<PathBuf>,
+    /// The amount of URLs the Bloom filter is sized for
+    pub bloom_capacity: usize,
+    /// The chance of the Bloom filter wrongly skipping a URL as seen, once at capacity
+    pub bloom_error_rate: f64,
+    /// Ordered allow and deny rules for URLs, the first to match wins (see `UrlRule`)
+    pub rules: Vec<String>,
+    /// The URLs to start crawling from when none are given on the command line, each optionally
+    /// followed by ` hops=<n> priority=<n>`
+    pub seeds: Vec<String>,
+    /// Files of seeds to crawl from, one per line in the same format as `seeds`
+    pub seed_files: Vec<PathBuf>,
+    /// If set, serve live progress on this address while crawling, as JSON at `/progress` and
+    /// server-sent events at `/progress/events`
+    pub progress_bind: Option<String>,
+    /// Where to save the URLs left to crawl when a crawl is stopped early, to carry on from with
+    /// `crawl --resume`
+    pub checkpoint_file: Option<PathBuf>,
+}
+
+impl Default for CrawlerConfig {
+    fn default() -> CrawlerConfig {
+        CrawlerConfig {
+            user_agent: String::from("hunter-searcher crawler/v0.1.0"),
+            delay_ms: 1000,
+            timeout_secs: 30,
+            max_retries: 3,
+            retry_base_ms: 500,
+            retry_max_ms: 30_000,
+            breaker_threshold: 5,
+            breaker_cooldown_secs: 60,
+            stale_secs: 60*60*24*4,
+            max_pages: 1,
+            max_hops: None,
+            max_pages_per_host: None,
+            max_bytes: None,
+            trap_max_url_length: 2048,
+            trap_max_segment_repeats: 3,
+            trap_max_pattern_urls: 1000,
+            trap_max_host_urls: 50_000,
+            bloom_file: None,
+            bloom_capacity: 10_000_000,
+            bloom_error_rate: 0.001,
+            rules: Vec::new(),
+            seeds: Vec::new(),
+            seed_files: Vec::new(),
+            progress_bind: None,
+            checkpoint_file: Some(PathBuf::from("crawl-checkpoint.jsonl")),
+        }
+    }
+}
+
+/// Where and how the axum server listens for connections
+#[derive(Clone,Debug,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address to bind the TCP listener to (`HUNTER_BIND`)
+    pub bind: String,
+    /// The port to bind the TCP listener to (`HUNTER_PORT`)
+    pub port: u16,
+    /// The path prefix all routes are served under (e.g. `/search-engine`), for running behind a
+    /// reverse proxy (`HUNTER_BASE_PATH`)
+    pub base_path: String,
+    /// If set, listen on this unix domain socket instead of TCP (`HUNTER_UNIX_SOCKET`)
+    pub unix_socket: Option<PathBuf>,
+    /// The URL the server is reached at publicly, including any base path, for links in the
+    /// OpenSearch description (`HUNTER_PUBLIC_URL`)
+    ///
+    /// This should be set whenever the server is reached through a proxy or by a name, as
+    /// otherwise it's the address the server listens on.
+    pub public_url: Option<String>,
+    /// The progress endpoint of a crawl (its `progress_bind`) to show on the `/crawl` dashboard
+    pub crawl_progress_url: Option<String>,
+    /// The bearer token for the admin API under `/api/admin`, which is disabled if this isn't
+    /// set (`HUNTER_ADMIN_TOKEN`)
+    pub admin_token: Option<String>,
+    /// How many sites can be submitted through `/submit` from one address an hour, with 0
+    /// turning the submission form off (over a unix socket all submitters share one address,
+    /// unless `trust_proxy_headers` is on)
+    pub submissions_per_hour: u32,
+    /// Take the client address from `X-Forwarded-For`, when running behind a reverse proxy
+    pub trust_proxy_headers: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind: String::from("0.0.0.0"),
+            port: 22001,
+            base_path: String::new(),
+            unix_socket: None,
+            public_url: None,
+            crawl_progress_url: None,
+            admin_token: None,
+            submissions_per_hour: 5,
+            trust_proxy_headers: false,
+        }
+    }
+}
+
+/// How search results are scored, as a weighted sum of signals:
+///
+/// ```text
+/// text_weight * relevance + freshness_weight * freshness + authority_weight * ln(1 + inbound links)
+///     - js_penalty * ln(1 + scripts) - url_depth_penalty * path segments
+/// ```
+///
+/// where relevance is the full text `ts_rank`, and freshness goes from 1 when the page was just
+/// indexed, halving every `freshness_half_life_days`.
+#[derive(Clone,Debug,Serialize,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RankingConfig {
+    /// The weight of how well the text matches the query
+    pub text_weight: f64,
+    /// The weight of how recently the page was indexed
+    pub freshness_weight: f64,
+    /// The days it takes for a page's freshness to halve, with 0 turning freshness off
+    pub freshness_half_life_days: f64,
+    /// Taken off times the natural log of one more than the scripts on the page, so each extra
+    /// script costs less than the one before (about 0.7 times this for one script, 2.4 for ten)
+    pub js_penalty: f64,
+    /// Taken off for every path segment in the URL, so home pages come before deep pages
+    pub url_depth_penalty: f64,
+    /// The weight of the links to the page found while crawling, for pages where that's known
+    pub authority_weight: f64,
+    /// Results scoring less than this are left out
+    pub min_score: f64,
+}
+
+impl Default for RankingConfig {
+    fn default() -> RankingConfig {
+        RankingConfig {
+            text_weight: 1.0,
+            freshness_weight: 0.05,
+            freshness_half_life_days: 30.0,
+            js_penalty: 0.01,
+            url_depth_penalty: 0.01,
+            authority_weight: 0.02,
+            min_score: 0.1,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from a file and the environment
+    ///
+    /// If no path is given, `hunter-searcher.toml` in the working directory is used if it
+    /// exists, and the defaults otherwise.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => { debug!("No config file found, using defaults"); Config::default() }
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Read a config from a TOML file
+    fn from_file(path: &Path) -> Result<Config, String> {
+        info!("Loading config from {}", path.display());
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read config file {}: {e}", path.display()))?;
+        toml::from_str(&text)
+            .map_err(|e| format!("Couldn't parse config file {}: {e}", path.display()))
+    }
+
+    /// Override settings with any environment variables that are set
+    fn apply_env(&mut self) -> Result<(), String> {
+        if let Ok(v) = env::var("POSTGRES_USER") { self.database.user = v }
+        if let Ok(v) = env::var("POSTGRES_PASSWORD") { self.database.password = v }
+        if let Ok(v) = env::var("POSTGRES_HOST") { self.database.host = v }
+        if let Ok(v) = env::var("POSTGRES_DB") { self.database.name = v }
+        if let Ok(v) = env::var("HUNTER_BIND") { self.server.bind = v }
+        if let Ok(v) = env::var("HUNTER_PORT") {
+            self.server.port = v.parse().map_err(|e| format!("Invalid HUNTER_PORT {v:?}: {e}"))?
+        }
+        if let Ok(v) = env::var("HUNTER_BASE_PATH") { self.server.base_path = v }
+        if let Ok(v) = env::var("HUNTER_UNIX_SOCKET") { self.server.unix_socket = Some(PathBuf::from(v)) }
+        if let Ok(v) = env::var("HUNTER_PUBLIC_URL") { self.server.public_url = Some(v) }
+        if let Ok(v) = env::var("HUNTER_ADMIN_TOKEN") { self.server.admin_token = Some(v) }
+        Ok(())
+    }
+
+    /// Render the config as TOML, with the database password and admin token hidden
+    pub fn to_toml(&self) -> String {
+        let mut shown = self.clone();
+        if !shown.database.password.is_empty() { shown.database.password = String::from("********") }
+        if shown.server.admin_token.is_some() { shown.server.admin_token = Some(String::from("********")) }
+        toml::to_string_pretty(&shown).expect("Failed to serialise config!")
+    }
+}
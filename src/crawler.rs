@@ -2,12 +2,25 @@ use reqwest::{self, Client};
 use url::Url;
 use chrono::Utc;
 use texting_robots::{Robot, get_robots_url};
-use std::collections::{HashMap, VecDeque};
-use std::time::Duration;
-use tokio::time::sleep;
-use log::{debug, info, error};
-
-use crate::db::DB;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::time::{sleep, sleep_until};
+use log::{debug, info, warn};
+
+use crate::backoff::{CircuitBreaker, RetryPolicy};
+use crate::config::CrawlerConfig;
+use crate::db::{DB, FetchKind, FetchOutcome, FetchRecord, Upsert};
+use crate::frontier::{CrawlBudget, Frontier, FrontierEntry};
+use crate::lang;
+use crate::rules::{UrlRules, Verdict};
+use crate::progress::CrawlProgress;
+use crate::seeds::Seed;
+use crate::seen::{BloomFilter, BloomSettings, SeenSet};
+use crate::traps::{TrapDetector, TrapLimits};
+use crate::error::{CrawlError, FetchError, ParseError, SetupError};
  
 /// Index Entry - A index entry format struct
 #[derive(Clone,Debug)]
@@ -24,6 +37,18 @@ pub struct IndexEntry {
     pub content: String, 
     /// A small blurb for the document (the 10th-30th words)
     pub blurb: String, 
+    /// The size of the document, in bytes
+    pub size: usize,
+    /// The language code of the document, if it could be worked out
+    pub lang: Option<String>,
+}
+
+/// The body of a successful fetch, with the headers that matter for indexing it
+#[derive(Clone,Debug)]
+pub struct Fetched {
+    pub body: String,
+    /// The `Content-Language` header
+    pub content_language: Option<String>,
 }
 
 /// Crawler - A simple web crawler class implementation
@@ -35,125 +60,426 @@ pub struct Crawler {
     client: Client,
     /// The store of `robots.txt` records, cached for efficient retrieval
     robot_records: HashMap<String, String>,
-    /// A vector containing the domains of the websites the crawler has scraped
-    websites: Vec<String>,
-    /// The max amount of documents to get, decided in a first in, first out queue
-    max_depth: i32,
-    /// Whether the whitelist is enabled or not
-    whitelist_en: bool,
-    /// The whitelist contains domains or other patterns that the crawler will check that a
-    /// document **must** have in it's URL
-    whitelist: Vec<String>,
-    /// Whether the blacklist is enabled or not
-    blacklist_en: bool,
-    /// The blacklist contains domains or other strings that the crawler will check that a
-    /// document **must not** have in it's URL 
-    ///
-    /// **This overrides the blacklist** 
-    blacklist: Vec<String>,
+    /// The domains of the websites the crawler has scraped
+    websites: HashSet<String>,
+    /// The limits on how many pages, hops and bytes the crawl covers
+    budget: CrawlBudget,
+    /// The ordered allow and deny rules URLs are checked against
+    rules: UrlRules,
+    /// The thresholds past which links are quarantined as crawl traps
+    trap_limits: TrapLimits,
+    /// If set, remember the URLs queued in a Bloom filter on disk rather than in memory
+    bloom_filter: Option<BloomSettings>,
+    /// The priorities of the URLs listed in the sitemaps of hosts crawled so far
+    sitemap_priorities: HashMap<String, f32>,
+    /// Where to report the crawl's progress, if anywhere
+    progress: Option<watch::Sender<CrawlProgress>>,
+    /// Turns true when the crawl should stop, and save what's left to the checkpoint
+    shutdown: Option<watch::Receiver<bool>>,
+    /// While this is true the crawl waits, without fetching anything
+    pause: Option<watch::Receiver<bool>>,
+    /// Where to save the frontier if the crawl is stopped early
+    checkpoint: Option<PathBuf>,
+    /// URLs from an earlier crawl's checkpoint to carry on with
+    resume: Vec<FrontierEntry>,
+    /// Seeds added while the crawl runs, taken onto the frontier as it goes
+    inbox: Option<Arc<Mutex<Vec<Seed>>>>,
     /// The time between requests
     delay_time: Duration,
     /// The in-memory index of all the documents gotten this crawl
     index: Vec<IndexEntry>,
     /// The time required for an entry to be considered stale, and replaced by the crawler
-    stale_time: i64
+    stale_time: i64,
+    /// The id of the current crawl in the fetch log, if it has started
+    crawl_id: Option<i64>,
+    /// How transient failures are retried
+    retry_policy: RetryPolicy,
+    /// The per-host circuit breaker, to stop requesting hosts that keep failing
+    circuit_breaker: CircuitBreaker,
 }
 
+/// The most times a URL is put back on the frontier while its host's circuit is open, before it's
+/// given up on
+const MAX_DEFERRALS: u32 = 3;
+
+/// The most sitemaps read from a host's `robots.txt`
+const MAX_SITEMAPS: usize = 3;
+
+/// How many pages the links found to are kept before they're added to the counts in the database
+const INBOUND_LINK_BATCH: usize = 1000;
+
 impl Crawler {
-    /// Crawl from a seed URL
-    pub async fn crawl(&mut self, db: &DB, seed_url: &str) -> Vec<IndexEntry> {
-        let mut to_get_links: VecDeque<String> = VecDeque::new();
-        to_get_links.push_back(seed_url.to_string());
+    /// Crawl from a list of seeds, highest scoring URLs first (see `Frontier`)
+    pub async fn crawl(&mut self, db: &DB, seeds: &[Seed]) -> Vec<IndexEntry> {
+        let seen = match &self.bloom_filter {
+            Some(bloom) => match BloomFilter::create(&bloom.path, bloom.capacity, bloom.error_rate) {
+                Ok(filter) => { info!("Tracking seen URLs in {}", bloom.path.display()); SeenSet::Bloom(filter) },
+                Err(e) => { warn!("Couldn't create Bloom filter {}, tracking seen URLs in memory: {e}", bloom.path.display()); SeenSet::default() }
+            },
+            None => SeenSet::default(),
+        };
+        let mut frontier = Frontier::new(self.budget.clone(), seen);
+        let mut traps = TrapDetector::new(self.trap_limits.clone());
+        for seed in seeds {
+            match Self::host_of(&seed.url) {
+                Ok(host) => { frontier.push(FrontierEntry::seed(seed.url.clone(), host, seed.max_hops, seed.priority), None); },
+                Err(e) => warn!("Can't crawl seed: {e}"),
+            }
+        }
+        for entry in std::mem::take(&mut self.resume) {
+            frontier.push(entry, None);
+        }
+        info!("Crawling from {} seeds", frontier.len());
+        let mut deferrals: HashMap<String, u32> = HashMap::new();
+        // Links found to pages that are crawled or already indexed, not yet saved
+        let mut found_links: HashMap<String, u32> = HashMap::new();
+
+        self.crawl_id = match db.start_crawl().await {
+            Ok(id) => { info!("Starting crawl {id}"); Some(id) },
+            Err(e) => { warn!("Couldn't record crawl, fetches won't be logged to it: {e}"); None }
+        };
+        let started = Instant::now();
+        let mut progress = CrawlProgress { crawl_id: self.crawl_id, running: true, started_at: Some(Utc::now()), ..Default::default() };
 
         debug!("Entering crawling loop...");
+        let mut stopped = false;
         loop {
-            if to_get_links.is_empty() { debug!("No links to get!"); break }
-            if self.max_depth != -1 && self.index.len() >= self.max_depth.try_into().unwrap() { info!("Exiting loop as depth limit reached..."); break }
-            
-            let url = to_get_links.pop_front().expect("no urls!");
-            let url_host = match Url::parse(&url) {
-                Ok(u) => {
-                    match u.host_str() {
-                        Some(h) => h.to_string(),
-                        None => { continue }
-                    }
-                },
-                Err(_) => { continue }
+            if let Some(reason) = frontier.exhausted() { info!("Exiting loop as {reason}..."); break }
+            if self.stopping() { info!("Stopping crawl early..."); stopped = true; break }
+            for seed in self.take_inbox() {
+                match Self::host_of(&seed.url) {
+                    Ok(host) => { frontier.push(FrontierEntry::seed(seed.url, host, seed.max_hops, seed.priority), None); },
+                    Err(e) => warn!("Can't crawl seed: {e}"),
+                }
+            }
+            if self.paused() {
+                info!("Crawl paused");
+                progress.paused = true;
+                self.report_progress(&mut progress, &frontier, started);
+                self.wait_while_paused().await;
+                progress.paused = false;
+                info!("Crawl unpaused");
+                continue
+            }
+
+            let entry = match frontier.pop() {
+                Some(entry) => entry,
+                None => match frontier.next_deferred() {
+                    Some(ready_at) => {
+                        info!("Waiting for {} URLs on paused hosts...", frontier.deferred_len());
+                        match self.shutdown.clone() {
+                            Some(shutdown) => tokio::select! {
+                                _ = sleep_until(ready_at.into()) => {},
+                                _ = crate::shutdown::requested(shutdown) => {},
+                            },
+                            None => sleep_until(ready_at.into()).await,
+                        }
+                        continue
+                    },
+                    None => { debug!("No links to get!"); break }
+                }
             };
+            let (url, url_host) = (entry.url.clone(), entry.host.clone());
+            progress.current_url = Some(url.clone());
+            progress.quarantined = traps.quarantined();
+            self.report_progress(&mut progress, &frontier, started);
+
+            if let Some(ready_at) = self.circuit_breaker.open_until(&url_host) {
+                let count = deferrals.entry(url.clone()).or_default();
+                *count += 1;
+                if *count > MAX_DEFERRALS {
+                    info!("Giving up on {url}, {url_host} is still failing");
+                    let mut record = Self::skip_record(&url, &url_host, FetchOutcome::CircuitOpen);
+                    record.error = Some(format!("{url_host} kept failing"));
+                    self.log_fetch(db, record).await;
+                } else {
+                    debug!("{url_host} is paused, putting {url} back for later");
+                    frontier.defer(entry, ready_at);
+                }
+                continue
+            }
 
             info!("Starting on {url}...");
 
-            if self.robot_records.contains_key(&url_host) {
-                let r = Robot::new(&self.user_agent, self.robot_records.get(&url_host).expect("failed to get key!").as_bytes()).unwrap();
-
-                if !r.allowed(url.as_str()) { info!("Robots.txt dissallowed this path!"); continue }
-            } else if let Some(resp) = self.request_body(&get_robots_url(url.as_str()).expect("failed to get robots.txt url!").to_string()).await {
-                let r = Robot::new(&self.user_agent, resp.as_bytes()).unwrap();
-                self.robot_records.insert(url_host.clone(), resp);
+            let verdict = self.rules.check(&url);
+            if !verdict.allowed() {
+                match verdict {
+                    Verdict::Rule { rule, .. } => info!("Skipping {url}, denied by rule {rule}"),
+                    Verdict::Default(_) => info!("Skipping {url}, no allow rule matched"),
+                }
+                self.log_fetch(db, Self::skip_record(&url, &url_host, FetchOutcome::Filtered)).await;
+                continue
+            }
 
-                if !r.allowed(url.as_str()) { info!("Robots.txt dissallowed this path!"); continue }
-            } else {
-                //TODO: mayhaps a default rebots.txt?
-                self.robot_records.insert(url_host.clone(), String::new());
+            if !self.robots_allowed(db, &url, &url_host).await {
+                info!("Robots.txt dissallowed this path!");
+                self.log_fetch(db, Self::skip_record(&url, &url_host, FetchOutcome::RobotsDenied)).await;
+                continue
             }
 
-            let mut skip: bool = false;
-            if self.blacklist_en {
-                for item in &self.blacklist {
-                    if url_host.contains(item) { info!("Website in blacklist!"); skip = true }
+            self.websites.insert(url_host.clone());
+            
+            let latest_index = match self.index_url(db, url.as_str()).await {
+                Ok(Some(latest_index)) => latest_index,
+                Ok(None) => continue,
+                Err(e) if self.stopping() => {
+                    info!("Putting {url} back for next time: {e}");
+                    frontier.defer(entry, Instant::now());
+                    continue
+                },
+                Err(e) => { warn!("Skipping {url}: {e}"); progress.errors += 1; continue }
+            };
+            frontier.record_page(&url_host, latest_index.size);
+
+            let mut links = Vec::new();
+            for link in &latest_index.links {
+                let Ok(host) = Self::host_of(link) else { continue };
+                if let Some(trap) = traps.check(link, &host) {
+                    if traps.quarantine(link) {
+                        debug!("Quarantining {link}: {trap}");
+                        let mut record = Self::skip_record(link, &host, FetchOutcome::Trap);
+                        record.error = Some(trap.to_string());
+                        self.log_fetch(db, record).await;
+                    }
+                    continue
                 }
+                links.push((link.clone(), host));
             }
-            if self.whitelist_en {
-                for item in &self.whitelist {
-                    if !url_host.contains(item) { info!("Website not in whitelist!"); skip = true }
+            let indexed = match db.last_indexed(links.iter().map(|(link, _)| link.clone()).collect()).await {
+                Ok(indexed) => indexed,
+                Err(e) => { warn!("Couldn't look up when links were indexed: {e}"); HashMap::new() }
+            };
+            for (link, host) in links {
+                let staleness = indexed.get(&link).map_or(1.0, |then| {
+                    ((Utc::now() - *then).num_seconds() as f32 / self.stale_time.max(1) as f32).clamp(0.0, 1.0)
+                });
+                let link_entry = FrontierEntry {
+                    url: link.clone(),
+                    host: host.clone(),
+                    depth: entry.depth + 1,
+                    max_hops: entry.max_hops,
+                    priority: entry.priority,
+                    sitemap_priority: self.sitemap_priorities.get(&link).copied(),
+                    staleness,
+                    inbound: 0,
+                };
+                if frontier.push(link_entry, Some(&url_host)) {
+                    traps.record(&link, &host);
+                } else if indexed.contains_key(&link) && !frontier.is_queued(&link) {
+                    // Links to queued URLs are counted in their entries, and saved once they're crawled
+                    *found_links.entry(link).or_default() += 1;
                 }
             }
-            if skip { info!("Skipping..."); continue }
 
-            Self::push_dedup(&mut self.websites, url_host);
-            
-            if let Some(latest_index) = self.index_url(db, url.as_str()).await{
-                for link in &latest_index.links {
-                    if !to_get_links.contains(link) {
-                        to_get_links.push_back(link.to_string())
-                    }
-                }
+            println!("Getting \"{:<60}\" ({:_>6} left, {:_>6} total sites)", latest_index.url, frontier.len(), self.websites.len());
 
-                println!("Getting \"{:<60}\" ({:_>6} left, {:_>6} total sites)", latest_index.url, to_get_links.len(), self.websites.len());
+            let i = latest_index.clone();
+            let number_js = i32::try_from(i.number_js).unwrap_or(i32::MAX);
+            if let Err(e) = db.add_webpage(i.title, i.url, i.blurb, i.content, number_js, i.lang, true).await {
+                warn!("Couldn't add {url} to database: {e}");
+                progress.errors += 1;
+                continue
+            }
+            if entry.inbound > 0 { *found_links.entry(url.clone()).or_default() += entry.inbound }
+            if found_links.len() >= INBOUND_LINK_BATCH { self.save_inbound_links(db, &mut found_links).await }
 
-                let i = latest_index.clone();
-                db.add_webpage(i.title, i.url, i.blurb, i.content, i.number_js.try_into().expect("Failed to convert!"), true).await;
+            self.index.push(latest_index)
+        }
 
-                self.index.push(latest_index)
+        info!("Crawled {} pages, out of {} URLs found", self.index.len(), frontier.seen_len());
+        if traps.quarantined() > 0 { info!("Quarantined {} URLs as likely crawl traps", traps.quarantined()) }
+        if let Some(path) = &self.checkpoint {
+            if stopped {
+                match frontier.checkpoint(path) {
+                    Ok(count) => info!("Saved {count} URLs left to crawl to {}, carry on with --resume", path.display()),
+                    Err(e) => warn!("Couldn't save the URLs left to crawl: {e}"),
+                }
+            } else if path.exists() {
+                if let Err(e) = std::fs::remove_file(path) { warn!("Couldn't remove old checkpoint {}: {e}", path.display()) }
             }
         }
+        progress.quarantined = traps.quarantined();
+        progress.current_url = None;
+        progress.running = false;
+        self.report_progress(&mut progress, &frontier, started);
+
+        self.save_inbound_links(db, &mut found_links).await;
+        if let Some(id) = self.crawl_id {
+            if let Err(e) = db.finish_crawl(id).await { warn!("Couldn't record end of crawl {id}: {e}") }
+        }
+        if let Err(e) = db.refresh_terms().await { warn!("Couldn't refresh the suggestion and spelling dictionaries: {e}") }
 
         self.index.clone()
     }
     
+    /// Whether the crawl has been asked to stop
+    fn stopping(&self) -> bool {
+        self.shutdown.as_ref().is_some_and(|shutdown| *shutdown.borrow())
+    }
+
+    /// Take any seeds added since the last time
+    fn take_inbox(&self) -> Vec<Seed> {
+        match &self.inbox {
+            Some(inbox) => std::mem::take(&mut *inbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner())),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether the crawl has been paused
+    fn paused(&self) -> bool {
+        self.pause.as_ref().is_some_and(|pause| *pause.borrow())
+    }
+
+    /// Wait until the crawl is unpaused, or asked to stop
+    async fn wait_while_paused(&self) {
+        let Some(mut pause) = self.pause.clone() else { return };
+        match self.shutdown.clone() {
+            Some(shutdown) => tokio::select! {
+                _ = pause.wait_for(|paused| !*paused) => {},
+                _ = crate::shutdown::requested(shutdown) => {},
+            },
+            None => { let _ = pause.wait_for(|paused| !*paused).await; }
+        }
+    }
+
+    /// Fill in the progress from the frontier, and report it if anything is listening
+    fn report_progress(&self, progress: &mut CrawlProgress, frontier: &Frontier, started: Instant) {
+        let Some(sender) = &self.progress else { return };
+        let elapsed = started.elapsed().as_secs_f64();
+        progress.elapsed_secs = elapsed;
+        progress.pages = frontier.pages();
+        progress.bytes = frontier.bytes();
+        progress.queued = frontier.len();
+        progress.deferred = frontier.deferred_len();
+        progress.seen = frontier.seen_len();
+        progress.hosts = self.websites.len();
+        progress.pages_per_sec = if elapsed > 0.0 { progress.pages as f64 / elapsed } else { 0.0 };
+        progress.bytes_per_sec = if elapsed > 0.0 { progress.bytes as f64 / elapsed } else { 0.0 };
+        sender.send_replace(progress.clone());
+    }
+
+    /// Check the host's `robots.txt` (fetching and caching it if needed) to see if a URL may be
+    /// crawled
+    ///
+    /// A missing or unparseable `robots.txt` allows everything.
+    async fn robots_allowed(&mut self, db: &DB, url: &str, url_host: &str) -> bool {
+        if !self.robot_records.contains_key(url_host) {
+            let robots = match get_robots_url(url) {
+                Ok(robots_url) => self.request_body(db, robots_url.as_str(), FetchKind::Robots).await.map(|fetched| fetched.body).unwrap_or_else(|e| {
+                    debug!("No robots.txt for {url_host}: {e}");
+                    //TODO: mayhaps a default rebots.txt?
+                    String::new()
+                }),
+                Err(e) => { debug!("Couldn't get robots.txt url for {url}: {e}"); String::new() }
+            };
+            self.read_sitemaps(db, url_host, &robots).await;
+            self.robot_records.insert(url_host.to_string(), robots);
+        }
+
+        let robots = &self.robot_records[url_host];
+        match Robot::new(&self.user_agent, robots.as_bytes()) {
+            Ok(r) => r.allowed(url),
+            Err(e) => { warn!("Couldn't parse robots.txt for {url_host}: {e}"); true }
+        }
+    }
+
+    /// Fetch the sitemaps listed in a host's `robots.txt`, remembering the priority of each URL in
+    /// them
+    async fn read_sitemaps(&mut self, db: &DB, url_host: &str, robots: &str) {
+        let sitemaps = match Robot::new(&self.user_agent, robots.as_bytes()) {
+            Ok(r) => r.sitemaps,
+            Err(_) => return,
+        };
+        for sitemap in sitemaps.into_iter().take(MAX_SITEMAPS) {
+            let body = match self.request_body(db, &sitemap, FetchKind::Sitemap).await {
+                Ok(fetched) => fetched.body,
+                Err(e) => { debug!("Couldn't get sitemap for {url_host}: {e}"); continue }
+            };
+            let priorities = Self::sitemap_priorities(&body);
+            debug!("Read {} URLs from {sitemap}", priorities.len());
+            self.sitemap_priorities.extend(priorities);
+        }
+    }
+
+    /// The URLs in a sitemap, with their priority (0.5 if it isn't given)
+    fn sitemap_priorities(body: &str) -> Vec<(String, f32)> {
+        let Ok(dom) = tl::parse(body, tl::ParserOptions::default()) else { return Vec::new() };
+        let parser = dom.parser();
+        let text = |tag: &tl::HTMLTag, name: &str| tag.query_selector(parser, name)
+            .and_then(|mut nodes| nodes.next())
+            .and_then(|node| node.get(parser))
+            .map(|node| node.inner_text(parser).trim().to_string());
+
+        dom.query_selector("url").into_iter().flatten()
+            .filter_map(|handle| handle.get(parser)?.as_tag())
+            .filter_map(|tag| {
+                let loc = text(tag, "loc")?;
+                let priority = text(tag, "priority").and_then(|p| p.parse().ok()).unwrap_or(0.5f32);
+                Some((loc, priority.clamp(0.0, 1.0)))
+            })
+            .collect()
+    }
+
     /// Index a single URL
-    pub async fn index_url(&self, db: &DB, url: &str) -> Option<IndexEntry> {
+    ///
+    /// Returns `Ok(None)` if the URL was indexed recently enough to be skipped.
+    pub async fn index_url(&mut self, db: &DB, url: &str) -> Result<Option<IndexEntry>, CrawlError> {
         info!("Indexing {url}...");
 
-        // TODO check in the DB if the url has been indexed lately, and skip if so
-        
         let now = Utc::now().timestamp();
-        let then = match db.get_webpage(url.to_string()).await {
+        let then = match db.get_webpage(url.to_string()).await? {
             Some(i) => i.timestamp.timestamp(),
             None => 0
         };
         let d = now-then;
         debug!("Timestamps are: {now}, {then}, {d} vs {}", self.stale_time);
-        if then + self.stale_time >= now { info!("not allowed #####"); return None; }
+        if then + self.stale_time >= now {
+            info!("Indexed recently, skipping {url}");
+            self.log_fetch(db, Self::skip_record(url, &Self::host_of(url)?, FetchOutcome::Stale)).await;
+            return Ok(None);
+        }
 
-        let resp = match self.request_body(url).await {
-            Some(resp) => resp,
-            None => { return None; }
-        };
+        self.fetch_page(db, url).await.map(Some)
+    }
+
+    /// Index a single URL now, however recently it was indexed, and store it
+    ///
+    /// The URL is still checked against `robots.txt`, but not the allow and deny rules.
+    pub async fn reindex(&mut self, db: &DB, url: &str) -> Result<Upsert, CrawlError> {
+        info!("Reindexing {url}...");
+        if !self.robots_allowed(db, url, &Self::host_of(url)?).await {
+            return Err(CrawlError::RobotsDenied { url: url.to_string() });
+        }
+        let page = self.fetch_page(db, url).await?;
+        let number_js = i32::try_from(page.number_js).unwrap_or(i32::MAX);
+        Ok(db.add_webpage(page.title, page.url, page.blurb, page.content, number_js, page.lang, true).await?)
+    }
+
+    /// Fetch and parse a page
+    async fn fetch_page(&mut self, db: &DB, url: &str) -> Result<IndexEntry, CrawlError> {
+        let fetched = self.request_body(db, url, FetchKind::Page).await?;
+
+        match Self::parse_page(url, &fetched.body, fetched.content_language.as_deref()) {
+            Ok(page) => Ok(page),
+            Err(err) => {
+                let mut record = Self::skip_record(url, &Self::host_of(url)?, FetchOutcome::Error);
+                record.error = Some(err.to_string());
+                self.log_fetch(db, record).await;
+                Err(err.into())
+            }
+        }
+    }
 
+    /// Parse a fetched page into an index entry
+    ///
+    /// This is kept apart from the fetching, as the parsed document isn't `Send` so can't be held
+    /// across an await.
+    fn parse_page(url: &str, resp: &str, content_language: Option<&str>) -> Result<IndexEntry, ParseError> {
         let mut page_urls: Vec<String> = Vec::new();
+        let mut page_seen: HashSet<String> = HashSet::new();
     
-        let dom = tl::parse(resp.as_str(), tl::ParserOptions::default()).unwrap();
+        let dom = tl::parse(resp, tl::ParserOptions::default())
+            .map_err(|source| ParseError::Html { url: url.to_string(), source })?;
         let parser = dom.parser();
 
         // TODO add decoding escaped html characters
@@ -162,14 +488,16 @@ impl Crawler {
         debug!("Constructing text...");
         let mut text: String = String::new();
         for tag in ["body", "title", "img[alt]"] {
-            for child in dom.query_selector(tag).unwrap() {
+            // Nodes the parser can't find or that aren't tags are skipped, rather than trusted
+            for child in dom.query_selector(tag).into_iter().flatten() {
                 if !tag.contains('[') {
-                    let child = child.get(parser).unwrap();
+                    let Some(child) = child.get(parser) else { continue };
                     text += &child.inner_text(parser);
                     text += " ";
 
-                    for sub in child.as_tag().unwrap().query_selector(parser, "script").unwrap() {
-                        text = text.replace(&sub.get(parser).unwrap().inner_text(parser).into_owned(), "");
+                    let scripts = child.as_tag().and_then(|tag| tag.query_selector(parser, "script"));
+                    for sub in scripts.into_iter().flatten().filter_map(|sub| sub.get(parser)) {
+                        text = text.replace(&sub.inner_text(parser).into_owned(), "");
                     }
 
                     if tag.contains("title") {
@@ -182,32 +510,37 @@ impl Crawler {
                         title = String::from_iter(temp_title[0..n].iter());
                     }
                 } else {
-                    let img = child.get(parser).unwrap();
-                    let text_portion = match img.as_tag() {
+                    let text_portion = match child.get(parser).and_then(|img| img.as_tag()) {
                         Some(imgtag) => { 
                             match imgtag.attributes().get("alt") {
-                                Some(text) => { match text {
-                                        Some(text) => text.try_as_utf8_str().unwrap(),
-                                        None => { continue }
-                                    }
+                                Some(Some(text)) => match text.try_as_utf8_str() {
+                                    Some(text) => text,
+                                    None => { continue }
                                 },
-                                None => { continue }
+                                _ => { continue }
                             } 
                         },
                         None => { continue }
                     };
-                    text += &text_portion;
+                    text += text_portion;
                     text += " ";
                 }
             }
         }
 
         let mut number_js = 0;
-        for _ in dom.query_selector("script").unwrap() {
+        for _ in dom.query_selector("script").into_iter().flatten() {
             number_js += 1;
         }
         let text: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
         debug!("{text}");
+
+        let html_lang = dom.query_selector("html[lang]").and_then(|mut html| html.next())
+            .and_then(|html| html.get(parser))
+            .and_then(|node| node.as_tag())
+            .and_then(|tag| tag.attributes().get("lang").flatten())
+            .and_then(|lang| lang.try_as_utf8_str());
+        let lang = lang::detect(html_lang, content_language, &text);
         
         let words = text.split_whitespace().collect::<Vec<_>>();
         
@@ -220,19 +553,15 @@ impl Crawler {
         else { blurb = words[10..30].join(" "); }
 
         debug!("Collecting links...");
-        for a in dom.query_selector("a[href]").unwrap(){
-            let mut href = a.get(parser)
-                     .expect("no node")
-                     .as_tag()
-                     .expect("no tag")
-                     .attributes()
-                     .get("href")
-                     .expect("no href")
-                     .expect("Attribute not found or malformed")
-                     .try_as_utf8_str()
-                     .expect("not utf8!")
-                     .to_string()
-                     .replace("&#x2F;", "/");
+        for a in dom.query_selector("a[href]").into_iter().flatten() {
+            let href = a.get(parser)
+                        .and_then(|node| node.as_tag())
+                        .and_then(|tag| tag.attributes().get("href").flatten())
+                        .and_then(|href| href.try_as_utf8_str());
+            let mut href = match href {
+                Some(href) => href.replace("&#x2F;", "/"),
+                None => { debug!("Skipping malformed link in {url}"); continue }
+            };
             
             if href == "/" ||
                href == "#" ||
@@ -252,53 +581,169 @@ impl Crawler {
             }
             if skip { continue; }
 
-            if href.starts_with("http://") || href.starts_with("https://") {
-                Self::push_dedup(&mut page_urls, href)
+            let link = if href.starts_with("http://") || href.starts_with("https://") {
+                href
             } else {
-                Self::push_dedup(&mut page_urls, Self::resolve_relative_url(url, href))
-            }
+                match Self::resolve_relative_url(url, &href) {
+                    Ok(link) => link,
+                    Err(e) => { debug!("Skipping link: {e}"); continue }
+                }
+            };
+            if page_seen.insert(link.clone()) { page_urls.push(link) }
         }
     
-        Some(IndexEntry{ 
+        Ok(IndexEntry{ 
             url: url.to_string(), 
             links: page_urls, 
             title,
             number_js, 
             content,
             blurb,
+            size: resp.len(),
+            lang,
         })
     }
     
-    /// Returns the body of a request as a string
-    pub async fn request_body(&self, url: &str) -> Option<String> {
-        let mut body: Option<String> = None;
-        let resp = match self.client.execute(
-                self.client.get(url).build().expect("failed to build request!")
-            )
-            .await {
-                Ok(r) => r,
-                Err(e) =>{ error!("Request failed to {url}: {e}"); return None; }
-            };
+    /// Returns the body of a request as a string, recording each attempt in the fetch log
+    ///
+    /// Timeouts, connection failures, 429s and 5xxs are retried with backoff, and count against
+    /// the host's circuit breaker if they keep failing. Pages that aren't served as HTML are
+    /// rejected.
+    pub async fn request_body(&mut self, db: &DB, url: &str, kind: FetchKind) -> Result<Fetched, FetchError> {
+        let host = Self::host_of(url).unwrap_or_default();
+        let html_only = kind == FetchKind::Page;
+        let mut retry = 0;
+        loop {
+            let started = Instant::now();
+            let body = self.fetch(url, html_only).await;
+            let latency = started.elapsed();
+
+            let mut record = Self::skip_record(url, &host, FetchOutcome::Ok);
+            record.kind = kind;
+            record.latency = Some(latency);
+            match &body {
+                Ok(fetched) => {
+                    record.status = Some(200);
+                    record.bytes = Some(fetched.body.len());
+                },
+                Err(e) => {
+                    record.outcome = match e {
+                        FetchError::Request { source, .. } | FetchError::Body { source, .. } if source.is_timeout() => FetchOutcome::Timeout,
+                        FetchError::Status { .. } => FetchOutcome::HttpError,
+                        FetchError::NotHtml { .. } => FetchOutcome::NotHtml,
+                        _ => FetchOutcome::Error,
+                    };
+                    record.status = match e {
+                        FetchError::Status { status, .. } => Some(status.as_u16()),
+                        FetchError::NotHtml { .. } => Some(200),
+                        _ => None,
+                    };
+                    record.error = Some(e.to_string());
+                }
+            }
+            self.log_fetch(db, record).await;
+
+            match &body {
+                Err(e) if e.is_transient() => {
+                    if retry < self.retry_policy.max_retries && !self.stopping() {
+                        retry += 1;
+                        let delay = self.retry_policy.delay(retry, e.retry_after()).max(self.delay_time);
+                        info!("{e}, retrying in {delay:?} ({retry}/{})", self.retry_policy.max_retries);
+                        sleep(delay).await;
+                        continue
+                    }
+                    self.circuit_breaker.record_failure(&host);
+                },
+                // The host is up, even if this URL isn't
+                _ => self.circuit_breaker.record_success(&host),
+            }
 
-        if resp.status() == 200 {
-            body = Some(resp.text().await.expect("error getting text"));
+            sleep(self.delay_time).await;
+            return body
         }
-        sleep(self.delay_time).await;
-        body
     }
 
-    /// Simple utility function that pushes a value if it's not already in the vector
-    fn push_dedup(vec: &mut Vec<String>, thing: String) {
-        if !vec.contains(&thing) {
-            vec.push(thing);
+    /// Fetch a URL, failing on anything but a 200 OK
+    async fn fetch(&self, url: &str, html_only: bool) -> Result<Fetched, FetchError> {
+        let resp = self.client.get(url).send().await
+            .map_err(|source| FetchError::Request { url: url.to_string(), source })?;
+
+        if resp.status() != 200 {
+            let retry_after = resp.headers().get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(Self::parse_retry_after);
+            return Err(FetchError::Status { url: url.to_string(), status: resp.status(), retry_after });
         }
+        if html_only {
+            let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            // Servers that don't say are given the benefit of the doubt
+            if !content_type.is_empty() && !content_type.contains("html") {
+                return Err(FetchError::NotHtml { url: url.to_string(), content_type: content_type.to_string() });
+            }
+        }
+        let content_language = resp.headers().get(reqwest::header::CONTENT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let body = resp.text().await
+            .map_err(|source| FetchError::Body { url: url.to_string(), source })?;
+        Ok(Fetched { body, content_language })
     }
-    
+
+    /// Parse a `Retry-After` header, either a number of seconds or an HTTP date
+    fn parse_retry_after(value: &str) -> Option<Duration> {
+        if let Ok(secs) = value.trim().parse::<u64>() {
+            return Some(Duration::from_secs(secs));
+        }
+        let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+        (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+    }
+
+    /// A fetch log record for a page that wasn't requested
+    fn skip_record(url: &str, host: &str, outcome: FetchOutcome) -> FetchRecord {
+        FetchRecord {
+            url: url.to_string(),
+            host: host.to_string(),
+            status: None,
+            bytes: None,
+            latency: None,
+            kind: FetchKind::Page,
+            outcome,
+            error: None,
+        }
+    }
+
+    /// Add a record to the fetch log, only warning if that fails
+    async fn log_fetch(&self, db: &DB, record: FetchRecord) {
+        if let Err(e) = db.log_fetch(self.crawl_id, &record).await {
+            warn!("Couldn't log fetch of {}: {e}", record.url);
+        }
+    }
+
+    /// Add the links found to pages to this crawl's counts in the database, only warning if that
+    /// fails, and start afresh
+    async fn save_inbound_links(&self, db: &DB, found_links: &mut HashMap<String, u32>) {
+        if let (Some(id), false) = (self.crawl_id, found_links.is_empty()) {
+            if let Err(e) = db.add_inbound_links(id, found_links).await { warn!("Couldn't record the links found to pages: {e}") }
+        }
+        found_links.clear();
+    }
+
+    /// Helper function to get the host of a URL
+    fn host_of(url: &str) -> Result<String, ParseError> {
+        let parsed = Url::parse(url).map_err(|source| ParseError::Url { url: url.to_string(), source })?;
+        match parsed.host_str() {
+            Some(host) => Ok(host.to_string()),
+            None => Err(ParseError::NoHost { url: url.to_string() }),
+        }
+    }
+
     /// Helper function to resolve a relative HREF from a document's URL
-    fn resolve_relative_url(url: &str, href: String) -> String {
-        Url::parse(url).expect("can't parse url")
-                       .join(href.as_str()).expect("can't join relative path!")
-                       .as_str().to_string()
+    fn resolve_relative_url(url: &str, href: &str) -> Result<String, ParseError> {
+        Url::parse(url).and_then(|base| base.join(href))
+                       .map(|joined| joined.as_str().to_string())
+                       .map_err(|source| ParseError::Url { url: href.to_string(), source })
     }
 }
 
@@ -310,39 +755,41 @@ pub struct CrawlerBuilder {
 
 #[allow(dead_code)]
 impl CrawlerBuilder {
-    /// Create a CrawlerBuilder with the specified user agent
-    pub fn new (user_agent: &str) -> CrawlerBuilder {
-        CrawlerBuilder {
+    /// Create a CrawlerBuilder with the specified user agent, failing if the HTTP client can't be
+    /// created
+    pub fn new (user_agent: &str) -> Result<CrawlerBuilder, SetupError> {
+        Ok(CrawlerBuilder {
             crawler: Crawler {
                 user_agent: user_agent.to_string(),
-                //TODO: only accept html
                 client: Client::builder().user_agent(user_agent)
-                               .build().unwrap(), 
+                               .timeout(Duration::from_secs(30))
+                               .build()?,
                 robot_records: HashMap::new(),
-                websites: Vec::new(),
-                max_depth: -1,
-                whitelist_en: false,
-                whitelist: Vec::new(),
-                blacklist_en: false,
-                blacklist: Vec::new(),
+                websites: HashSet::new(),
+                budget: CrawlBudget::default(),
+                rules: UrlRules::default(),
                 delay_time: Duration::from_millis(1000),
                 index: Vec::new(),
                 stale_time: 60*60*24*4,
+                crawl_id: None,
+                retry_policy: RetryPolicy::default(),
+                circuit_breaker: CircuitBreaker::new(5, Duration::from_secs(60)),
+                trap_limits: TrapLimits::default(),
+                bloom_filter: None,
+                sitemap_priorities: HashMap::new(),
+                progress: None,
+                shutdown: None,
+                pause: None,
+                checkpoint: None,
+                resume: Vec::new(),
+                inbox: None,
             }
-        }
+        })
     }
 
-    /// Add a whitelist to the Crawler
-    pub fn add_whitelist(mut self, whitelist: Vec<String>) -> CrawlerBuilder {
-        self.crawler.whitelist_en = true;
-        self.crawler.whitelist = whitelist.to_owned();
-        self
-    }
-    
-    /// Add a blacklist to the Crawler
-    pub fn add_blacklist(mut self, blacklist: Vec<String>) -> CrawlerBuilder {
-        self.crawler.blacklist_en = true;
-        self.crawler.blacklist = blacklist.to_owned();
+    /// Set the ordered allow and deny rules URLs must pass to be crawled
+    pub fn rules(mut self, rules: UrlRules) -> CrawlerBuilder {
+        self.crawler.rules = rules;
         self
     }
     
@@ -352,16 +799,183 @@ impl CrawlerBuilder {
         self
     }
 
-    /// Adjust the maximum max depth of the crawl
+    /// Adjust the default (4 days) time, in seconds, before an indexed page is considered stale
+    /// and crawled again
+    pub fn stale_time(mut self, seconds: i64) -> CrawlerBuilder {
+        self.crawler.stale_time = seconds;
+        self
+    }
+
+    /// Adjust the default (30s) time before a request is given up on, failing if the HTTP client
+    /// can't be created
+    pub fn timeout(mut self, timeout: Duration) -> Result<CrawlerBuilder, SetupError> {
+        self.crawler.client = Client::builder().user_agent(self.crawler.user_agent.as_str())
+                                     .timeout(timeout)
+                                     .build()?;
+        Ok(self)
+    }
+
+    /// Adjust how transient failures are retried (by default 3 times, from 500ms up to 30s apart)
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> CrawlerBuilder {
+        self.crawler.retry_policy = retry_policy;
+        self
+    }
+
+    /// Adjust the default (5) failures in a row before requests to a host are paused, and the
+    /// default (60s) time they're paused for
+    pub fn circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> CrawlerBuilder {
+        self.crawler.circuit_breaker = CircuitBreaker::new(threshold, cooldown);
+        self
+    }
+
+    /// Adjust the max amount of pages to crawl
     ///
     /// By default this is -1, which means no limit
-    pub fn max_depth(mut self, depth: i32) -> CrawlerBuilder {
-        self.crawler.max_depth = depth;
+    pub fn max_pages(mut self, pages: i32) -> CrawlerBuilder {
+        self.crawler.budget.max_pages = usize::try_from(pages).ok();
+        self
+    }
+
+    /// Limit the amount of links followed from a seed, by default there is no limit
+    pub fn max_hops(mut self, hops: u32) -> CrawlerBuilder {
+        self.crawler.budget.max_hops = Some(hops);
+        self
+    }
+
+    /// Limit the amount of pages crawled from any one host, by default there is no limit
+    pub fn max_pages_per_host(mut self, pages: usize) -> CrawlerBuilder {
+        self.crawler.budget.max_pages_per_host = Some(pages);
+        self
+    }
+
+    /// Limit the amount of bytes downloaded, by default there is no limit
+    pub fn max_bytes(mut self, bytes: u64) -> CrawlerBuilder {
+        self.crawler.budget.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Adjust the thresholds past which links are quarantined as crawl traps (see `TrapLimits`)
+    pub fn trap_limits(mut self, limits: TrapLimits) -> CrawlerBuilder {
+        self.crawler.trap_limits = limits;
+        self
+    }
+
+    /// Remember the URLs queued in a Bloom filter on disk instead of in memory, for very large
+    /// crawls
+    pub fn bloom_filter(mut self, bloom: BloomSettings) -> CrawlerBuilder {
+        self.crawler.bloom_filter = Some(bloom);
+        self
+    }
+
+    /// Report the crawl's progress on a channel as it goes
+    pub fn progress(mut self, sender: watch::Sender<CrawlProgress>) -> CrawlerBuilder {
+        self.crawler.progress = Some(sender);
+        self
+    }
+
+    /// Stop the crawl once `shutdown` turns true, letting the current fetch finish
+    pub fn shutdown(mut self, shutdown: watch::Receiver<bool>) -> CrawlerBuilder {
+        self.crawler.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Pause the crawl while `pause` is true
+    pub fn pause(mut self, pause: watch::Receiver<bool>) -> CrawlerBuilder {
+        self.crawler.pause = Some(pause);
         self
     }
 
+    /// Take seeds added to `inbox` while the crawl runs onto the frontier
+    pub fn inbox(mut self, inbox: Arc<Mutex<Vec<Seed>>>) -> CrawlerBuilder {
+        self.crawler.inbox = Some(inbox);
+        self
+    }
+
+    /// Save the URLs left to crawl to a file if the crawl is stopped early (and remove it if the
+    /// crawl finishes)
+    pub fn checkpoint(mut self, path: PathBuf) -> CrawlerBuilder {
+        self.crawler.checkpoint = Some(path);
+        self
+    }
+
+    /// Carry on from URLs saved to a checkpoint, crawling them along with the seeds
+    pub fn resume(mut self, entries: Vec<FrontierEntry>) -> CrawlerBuilder {
+        self.crawler.resume = entries;
+        self
+    }
+
+    /// Create a CrawlerBuilder with the settings from a crawler config, failing if its rules are
+    /// invalid or the HTTP client can't be created
+    ///
+    /// The seeds, progress endpoint and checkpoint aren't set, as those depend on how the crawl
+    /// is run.
+    pub fn from_config(config: &CrawlerConfig) -> Result<CrawlerBuilder, SetupError> {
+        let mut builder = CrawlerBuilder::new(&config.user_agent)?
+                              .max_pages(config.max_pages)
+                              .delay_time(Duration::from_millis(config.delay_ms))
+                              .timeout(Duration::from_secs(config.timeout_secs))?
+                              .retry_policy(RetryPolicy {
+                                  max_retries: config.max_retries,
+                                  base_delay: Duration::from_millis(config.retry_base_ms),
+                                  max_delay: Duration::from_millis(config.retry_max_ms),
+                              })
+                              .circuit_breaker(config.breaker_threshold, Duration::from_secs(config.breaker_cooldown_secs))
+                              .stale_time(config.stale_secs)
+                              .rules(UrlRules::parse(&config.rules)?);
+        if let Some(max_hops) = config.max_hops {
+            builder = builder.max_hops(max_hops);
+        }
+        if let Some(max_pages_per_host) = config.max_pages_per_host {
+            builder = builder.max_pages_per_host(max_pages_per_host);
+        }
+        if let Some(max_bytes) = config.max_bytes {
+            builder = builder.max_bytes(max_bytes);
+        }
+        builder = builder.trap_limits(TrapLimits::from_config(config));
+        if let Some(path) = &config.bloom_file {
+            builder = builder.bloom_filter(BloomSettings {
+                path: path.clone(),
+                capacity: config.bloom_capacity,
+                error_rate: config.bloom_error_rate,
+            });
+        }
+        Ok(builder)
+    }
+
     /// Build the Crawler from the CrawlerBuilder
     pub fn build(&self) -> Crawler {
         self.crawler.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_html_is_parsed_without_panicking() {
+        let pages = [
+            "",
+            "<body",
+            "<html><body><img alt><a href>x</a><a href=\"/ok\">ok</a><script>",
+            "<title><title></body><body><script></script></img alt=\"x\">",
+            "<a href=\"http://[bad\">bad</a><body>\u{0}\u{fffd}</body>",
+        ];
+        for page in pages {
+            assert!(Crawler::parse_page("https://example.com/", page, None).is_ok(), "{page:?}");
+        }
+    }
+
+    #[test]
+    fn page_text_links_and_scripts_are_collected() {
+        let page = "<html><head><title>Hello</title><script>var x;</script></head>\
+                    <body><p>Some words</p><img alt=\"a cat\"><script>alert(1)</script>\
+                    <a href=\"/a\">a</a><a href=\"/a\">again</a></body></html>";
+        let entry = Crawler::parse_page("https://example.com/", page, None).unwrap();
+        assert_eq!(entry.title, "Hello");
+        assert_eq!(entry.number_js, 2);
+        assert_eq!(entry.links, vec![String::from("https://example.com/a")]);
+        assert!(entry.content.contains("Some words") && entry.content.contains("a cat"));
+        assert!(!entry.content.contains("alert"));
+    }
+}
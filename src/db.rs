@@ -1,35 +1,56 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
 use sqlx::types::chrono::{self, Utc};
+use std::collections::{BTreeMap, HashMap};
 use sqlx::{Pool, Postgres};
 use sqlx::postgres::PgPoolOptions;
-use log::{warn, info, debug};
-
-/*
-CREATE TABLE IF NOT EXISTS webpages (
-    id serial PRIMARY KEY,
-    title TEXT NOT NULL,
-    blurb TEXT,
-    content TEXT NOT NULL,
-    number_js INTEGER NOT NULL,
-    url TEXT NOT NULL,
-    search_vector tsvector,
-    timestamp timestamptz NOT NULL default now()
-);
-CREATE INDEX ix_search_vector ON webpages USING GIN (search_vector);
-CREATE OR REPLACE FUNCTION update_webpage_content() RETURNS trigger AS $$
-BEGIN
-    new.search_vector := setweight(to_tsvector(coalesce(new.title, '')), 'A') ||
-        setweight(to_tsvector(coalesce(new.blurb, '')), 'B') ||
-        setweight(to_tsvector(coalesce(new.content, '')), 'C') ||
-        setweight(to_tsvector(coalesce(new.url, '')), 'D');
-    return new;
-END
-$$ LANGUAGE plpgsql;
-CREATE TRIGGER webpage_search_vector_update
-BEFORE INSERT OR UPDATE
-ON webpages
-FOR EACH ROW EXECUTE PROCEDURE update_webpage_content();
-*/
+use log::{info, debug};
+
+use crate::config::{DatabaseConfig, RankingConfig};
+use crate::error::{QueryError, StorageError};
+use crate::query::ParsedQuery;
+
+/// The longest search query accepted, in characters
+const MAX_QUERY_LENGTH: usize = 512;
+
+/// The most suggestions given for a partly typed query
+pub const MAX_SUGGESTIONS: usize = 10;
+
+/// Searches with fewer results than this get a spelling correction suggested
+const SPARSE_RESULTS: usize = 3;
+
+/// Shorter words aren't matched by prefix in fuzzy searches, as they'd match almost everything
+const MIN_PREFIX_LENGTH: usize = 2;
+
+/// The most results a fuzzy search gives, as loose matches can take in most of the index
+const MAX_FUZZY_RESULTS: i64 = 100;
+
+/// How search terms are matched
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Full text search, matching whole (stemmed) words
+    #[default]
+    Standard,
+    /// Match words by prefix, and titles and URLs by trigram similarity, for partial names and
+    /// identifiers
+    Fuzzy,
+    /// Standard, falling back to fuzzy if that finds nothing
+    Auto,
+}
+
+impl std::str::FromStr for SearchMode {
+    type Err = QueryError;
+
+    fn from_str(mode: &str) -> Result<SearchMode, QueryError> {
+        match mode {
+            "" | "standard" => Ok(SearchMode::Standard),
+            "fuzzy" => Ok(SearchMode::Fuzzy),
+            "auto" => Ok(SearchMode::Auto),
+            _ => Err(QueryError::UnknownMode { mode: mode.to_string() }),
+        }
+    }
+}
 
 /// Struct for storing a search result into memory from the database
 #[derive(Debug,Serialize)]
@@ -40,146 +61,1030 @@ pub struct SearchResult {
     number_js: i32,
     rank: Option<f32>,
     pub timestamp: chrono::DateTime<Utc>,
+    /// What the rank is made up of, for explaining it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<ScoreComponents>,
+}
+
+/// How much each signal added to a search result's score, with penalties negative (see
+/// `RankingConfig`)
+#[derive(Clone,Debug,Serialize)]
+pub struct ScoreComponents {
+    /// The text search configuration the page was indexed with
+    pub config: String,
+    /// The `ts_rank` of each part of the page alone, before the text weight
+    pub fields: FieldRanks,
+    pub text: f64,
+    pub freshness: f64,
+    pub js: f64,
+    pub depth: f64,
+    pub authority: f64,
+    pub total: f64,
+}
+
+/// The `ts_rank` of the query against each part of a page, weighted as in the search vector
+#[derive(Clone,Debug,Serialize)]
+pub struct FieldRanks {
+    /// Weight A
+    pub title: f64,
+    /// Weight B
+    pub blurb: f64,
+    /// Weight C
+    pub content: f64,
+    /// Weight D
+    pub url: f64,
+}
+
+/// How a query was understood, for explaining its results
+#[derive(Debug,Serialize)]
+pub struct QueryExplanation {
+    pub parsed: ParsedQuery,
+    pub mode: SearchMode,
+    /// The `tsquery` the terms were turned into for each text search configuration in the index
+    pub tsquery: BTreeMap<String, String>,
+    pub weights: RankingConfig,
+}
+
+/// A search result with its score broken down, as it comes from the database
+struct RankedRow {
+    title: String,
+    url: String,
+    blurb: Option<String>,
+    number_js: i32,
+    timestamp: chrono::DateTime<Utc>,
+    config: String,
+    rank_a: f64,
+    rank_b: f64,
+    rank_c: f64,
+    rank_d: f64,
+    text: f64,
+    freshness: f64,
+    js: f64,
+    depth: f64,
+    authority: f64,
+    total: f64,
+}
+
+impl From<RankedRow> for SearchResult {
+    fn from(row: RankedRow) -> SearchResult {
+        let RankedRow { title, url, blurb, number_js, timestamp, config, rank_a, rank_b, rank_c, rank_d,
+                        text, freshness, js, depth, authority, total } = row;
+        let fields = FieldRanks { title: rank_a, blurb: rank_b, content: rank_c, url: rank_d };
+        SearchResult {
+            title, url, blurb, number_js, timestamp,
+            rank: Some(total as f32),
+            score: Some(ScoreComponents { config, fields, text, freshness, js, depth, authority, total }),
+        }
+    }
+}
+
+/// Struct for a whole webpage as stored in the database, used in exporting and importing the
+/// index
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct ExportedPage {
+    pub url: String,
+    pub title: String,
+    pub blurb: Option<String>,
+    pub content: String,
+    pub number_js: i32,
+    /// The page's language code, missing from dumps made before languages were detected
+    #[serde(default)]
+    pub lang: Option<String>,
+    pub timestamp: chrono::DateTime<Utc>,
+}
+
+/// Struct for summary statistics about the index
+#[derive(Debug)]
+pub struct IndexStats {
+    pub pages: i64,
+    pub hosts: i64,
+    pub number_js: i64,
+    pub oldest: Option<chrono::DateTime<Utc>>,
+    pub newest: Option<chrono::DateTime<Utc>>,
+}
+
+/// The outcome of adding a webpage to the database, with the id of its row
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Upsert {
+    /// The url wasn't in the database, and a new row was added
+    Inserted(i32),
+    /// The url was already in the database, and its row was changed
+    Updated(i32),
+    /// The url was already in the database with the same content
+    Unchanged(i32),
+}
+
+/// The outcome of adding a submitted site to the moderation queue
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Submitted {
+    /// The site was added to the queue, with the id of its row
+    Queued(i64),
+    /// The site is already waiting for review
+    Duplicate,
+    /// The submitter has already sent as many sites as they can this hour
+    RateLimited,
+}
+
+/// What happened when the crawler tried to fetch a URL
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum FetchOutcome {
+    /// The page was fetched and parsed
+    Ok,
+    /// The host's `robots.txt` doesn't allow the URL
+    RobotsDenied,
+    /// The URL was excluded by the allow and deny rules
+    Filtered,
+    /// The page was indexed recently, so wasn't fetched again
+    Stale,
+    /// The server responded with an error status
+    HttpError,
+    /// The request timed out
+    Timeout,
+    /// The response wasn't an HTML document
+    NotHtml,
+    /// The host kept failing, so the URL was given up on without being requested
+    CircuitOpen,
+    /// The URL looked like a crawl trap, so was quarantined without being requested
+    Trap,
+    /// Anything else, like connection failures or unparseable pages
+    Error,
+}
+
+impl FetchOutcome {
+    /// The name stored in the `outcome` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetchOutcome::Ok => "ok",
+            FetchOutcome::RobotsDenied => "robots_denied",
+            FetchOutcome::Filtered => "filtered",
+            FetchOutcome::Stale => "stale",
+            FetchOutcome::HttpError => "http_error",
+            FetchOutcome::Timeout => "timeout",
+            FetchOutcome::NotHtml => "not_html",
+            FetchOutcome::CircuitOpen => "circuit_open",
+            FetchOutcome::Trap => "trap",
+            FetchOutcome::Error => "error",
+        }
+    }
+}
+
+/// What a URL was fetched for
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum FetchKind {
+    /// A page to index
+    Page,
+    /// A host's `robots.txt`
+    Robots,
+    /// A sitemap listed in a `robots.txt`
+    Sitemap,
+}
+
+impl FetchKind {
+    /// The name stored in the `kind` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetchKind::Page => "page",
+            FetchKind::Robots => "robots",
+            FetchKind::Sitemap => "sitemap",
+        }
+    }
+}
+
+/// A single fetch attempt, as recorded in the fetch log
+#[derive(Debug,Clone)]
+pub struct FetchRecord {
+    pub url: String,
+    pub host: String,
+    /// The HTTP status, if there was a response
+    pub status: Option<u16>,
+    /// The size of the body, if it was downloaded
+    pub bytes: Option<usize>,
+    /// How long the request took, if one was made
+    pub latency: Option<std::time::Duration>,
+    pub kind: FetchKind,
+    pub outcome: FetchOutcome,
+    pub error: Option<String>,
+}
+
+/// Where a site submission is up to
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Serialize,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl SubmissionStatus {
+    /// The name stored in the `status` column
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubmissionStatus::Pending => "pending",
+            SubmissionStatus::Approved => "approved",
+            SubmissionStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// A site submitted through the submission form
+#[derive(Debug,Clone,Serialize)]
+pub struct Submission {
+    pub id: i64,
+    pub url: String,
+    pub note: Option<String>,
+    pub submitter: String,
+    pub submitted_at: chrono::DateTime<Utc>,
+    pub status: String,
+    pub reviewed_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// The results of a search, with a corrected query to suggest if there were few
+#[derive(Debug,Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub results: Vec<SearchResult>,
+    /// The query with misspelled words corrected, if that finds more
+    pub suggestion: Option<String>,
+    /// The mode the results came from, for `auto` whichever of standard or fuzzy found them
+    #[serde(skip)]
+    pub mode: SearchMode,
+    /// How the query was understood, when asked for
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub debug: Option<QueryExplanation>,
+}
+
+/// Struct for the start and end of a crawl
+#[derive(Debug)]
+pub struct CrawlSummary {
+    pub id: i64,
+    pub started_at: chrono::DateTime<Utc>,
+    pub finished_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// Struct for the amount of fetches with an outcome
+#[derive(Debug)]
+pub struct OutcomeCount {
+    pub outcome: String,
+    pub count: i64,
+}
+
+/// Struct for the error rate of a host
+#[derive(Debug)]
+pub struct HostErrors {
+    pub host: String,
+    pub fetches: i64,
+    pub errors: i64,
+    pub error_rate: f64,
+}
+
+/// Struct for the response times of a host
+#[derive(Debug)]
+pub struct HostLatency {
+    pub host: String,
+    pub fetches: i64,
+    pub avg_ms: f64,
+    pub max_ms: i32,
+}
+
+/// Struct for a path that failed to fetch, across every host
+#[derive(Debug)]
+pub struct FailingPath {
+    pub path: String,
+    pub failures: i64,
+    pub hosts: i64,
+}
+
+/// Struct for a summary of a crawl, built from the fetch log
+#[derive(Debug)]
+pub struct CrawlReport {
+    pub crawl: CrawlSummary,
+    pub outcomes: Vec<OutcomeCount>,
+    pub error_hosts: Vec<HostErrors>,
+    pub slow_hosts: Vec<HostLatency>,
+    pub failing_paths: Vec<FailingPath>,
 }
 
 /// Simple struct to hold the database connection pool
+#[derive(Clone)]
 pub struct DB {
     pool: Pool<Postgres>,
+    /// How search results are scored
+    ranking: RankingConfig,
 }
 
 impl DB {
-    /// Connect to the database using a username, password, host (including port) and endpoint (or
-    /// database name)
-    pub async fn new(un: &str, pw: &str, hs: &str, ep: &str) -> DB {
+    /// Connect to the database using the username, password, host (including port) and database
+    /// name from the config
+    pub async fn new(config: &DatabaseConfig) -> Result<DB, StorageError> {
+        let DatabaseConfig { user, password, host, name, pool_size } = config;
         let pool = PgPoolOptions::new()
-                        .max_connections(5)
+                        .max_connections(*pool_size)
                         .connect(
-                            format!("postgres://{un}:{pw}@{hs}/{ep}").as_str()
-                            ).await
-                        .expect("Failed to connect to postgres server!");
-
-        // Run setup tasks
-        match sqlx::query!(r#"
-        CREATE TABLE IF NOT EXISTS webpages (
-            id serial PRIMARY KEY,
-            title TEXT NOT NULL,
-            blurb TEXT,
-            content TEXT NOT NULL,
-            number_js INTEGER NOT NULL,
-            url TEXT NOT NULL,
-            search_vector tsvector,
-            timestamp timestamptz NOT NULL default now()
-        );"#).execute(&pool).await {
-            Err(err) => println!("Failed on create table: {err:?}"),
-            Ok(_) => {},
-        }
-        match sqlx::query!(
-                "CREATE INDEX IF NOT EXISTS ix_search_vector ON webpages USING GIN (search_vector);"
-                ).execute(&pool).await { 
-            Err(err) => println!("failed on create index: {err:?}"),
-            Ok(_) => {},
-        }
-        
-        match sqlx::query!(r#"CREATE OR REPLACE FUNCTION update_webpage_content() RETURNS trigger AS $$
-        BEGIN
-            new.search_vector := setweight(to_tsvector(coalesce(new.title, '')), 'A') ||
-                setweight(to_tsvector(coalesce(new.blurb, '')), 'B') ||
-                setweight(to_tsvector(coalesce(new.content, '')), 'C') ||
-                setweight(to_tsvector(coalesce(new.url, '')), 'D');
-            return new;
-        END
-        $$ LANGUAGE plpgsql;"#).execute(&pool).await {
-            Err(err) => println!("failed on create function: {err:?}"),
-            Ok(_) => {},
-        }
-
-        match sqlx::query!(
-                "DROP TRIGGER IF EXISTS webpage_search_vector_update ON webpages;"
-                ).execute(&pool).await {
-            Err(err) => println!("Failed to drop existing trigger: {err:?}"),
-            Ok(_) => {},
-        }
-
-        match sqlx::query!(r#"
-                CREATE TRIGGER webpage_search_vector_update
-                BEFORE INSERT OR UPDATE
-                ON webpages
-                FOR EACH ROW EXECUTE PROCEDURE update_webpage_content();"#
-                ).execute(&pool).await {
-            Ok(_) => {},
-            Err(err) => println!("failed on create trigger: {err:?}"),
-        }
-
-        DB {
-            pool
-        }
-    }
-
-    /// Execute a search on the database from a search term
-    pub async fn search(&self, input: &str) -> Option<Vec<SearchResult>>{
-
-        // TODO add other search parameters (-, site:, type:, etc)
-
-        match sqlx::query_as!(SearchResult, r#" 
-                SELECT title, url, blurb, number_js, rank, timestamp
-                FROM (select title, url, blurb, number_js, ts_rank(search_vector, websearch_to_tsquery($1)) AS rank, timestamp FROM webpages)
-                WHERE rank > 0.1
-                ORDER BY rank DESC"#, input
-                ).fetch_all(&self.pool).await {
-            Ok(results) => Some(results),
-            Err(_) => None
-        }
-    }
-
-    /// Adds a webpage to the database
-    pub async fn add_webpage(&self, title: String, url: String, blurb: String, content: String, number_js: i32, ovrride: bool) {
-        debug!("Adding {url} to database...");
+                            format!("postgres://{user}:{password}@{host}/{name}").as_str()
+                            ).await?;
 
-        match sqlx::query_as!(TCU, "SELECT title, url, content FROM webpages WHERE url = $1", url).fetch_one(&self.pool).await {
-            Ok(res) => {
-                if res.title == title && 
-                   res.content == content && 
-                   res.url == url &&
-                   !ovrride
-                   { info!("Already in database, skipping..."); return; }
-                else if res.url == url {
-                    debug!("Database entry stale, deleting...");
-                    self.drop_index(res.url).await;
-                }
+        Ok(DB {
+            pool,
+            ranking: RankingConfig::default(),
+        })
+    }
+
+    /// Score search results with different weights
+    pub fn with_ranking(mut self, ranking: RankingConfig) -> DB {
+        self.ranking = ranking;
+        self
+    }
+
+    /// The weights search results are scored with
+    pub fn ranking(&self) -> &RankingConfig {
+        &self.ranking
+    }
+
+    /// Close the connection pool, waiting for any queries still running to finish
+    pub async fn close(&self) {
+        self.pool.close().await
+    }
+
+    /// Apply any schema migrations (from the `migrations/` directory) that haven't been run yet
+    pub async fn migrate(&self) -> Result<(), StorageError> {
+        Ok(sqlx::migrate!().run(&self.pool).await?)
+    }
+
+    /// Execute a search on the database from a search term, returning the results and the mode
+    /// they came from
+    pub async fn search(&self, input: &str, mode: SearchMode) -> Result<(Vec<SearchResult>, SearchMode), QueryError> {
+
+        // TODO add other search parameters (site:, type:, etc)
+
+        let input = input.trim();
+        if input.chars().count() > MAX_QUERY_LENGTH { return Err(QueryError::TooLong { max: MAX_QUERY_LENGTH }) }
+        let query = ParsedQuery::parse(input)?;
+        if query.terms.is_empty() { return Err(QueryError::Empty) }
+
+        match mode {
+            SearchMode::Standard => Ok((self.standard_search(&query).await?, mode)),
+            SearchMode::Fuzzy => Ok((self.fuzzy_search(&query).await?, mode)),
+            SearchMode::Auto => {
+                let results = self.standard_search(&query).await?;
+                if !results.is_empty() { return Ok((results, SearchMode::Standard)) }
+                Ok((self.fuzzy_search(&query).await?, SearchMode::Fuzzy))
             }
-            Err(_) => {}
+        }
+    }
+
+    /// Full text search for the words of a query, scored as set in the `RankingConfig`
+    ///
+    /// The terms are parsed with each page's own text search configuration, so they're stemmed
+    /// the same way as the page was, whatever its language.
+    async fn standard_search(&self, query: &ParsedQuery) -> Result<Vec<SearchResult>, QueryError> {
+        let r = &self.ranking;
+        // The terms are turned into a tsquery once for each text search configuration, rather
+        // than for each page, so that the index on search_vector can be used
+        let rows = sqlx::query_as!(RankedRow, r#"
+                SELECT title, url, blurb, number_js, timestamp, config AS "config!",
+                       rank_a AS "rank_a!", rank_b AS "rank_b!", rank_c AS "rank_c!", rank_d AS "rank_d!",
+                       text AS "text!", freshness AS "freshness!", js AS "js!", depth AS "depth!", authority AS "authority!",
+                       text + freshness + js + depth + authority AS "total!"
+                FROM (
+                    SELECT title, url, blurb, number_js, timestamp, search_config::text AS config,
+                           ts_rank('{0, 0, 0, 1}', search_vector, terms)::float8 AS rank_a,
+                           ts_rank('{0, 0, 0.4, 0}', search_vector, terms)::float8 AS rank_b,
+                           ts_rank('{0, 0.2, 0, 0}', search_vector, terms)::float8 AS rank_c,
+                           ts_rank('{0.1, 0, 0, 0}', search_vector, terms)::float8 AS rank_d,
+                           $3::float8 * ts_rank(search_vector, terms) AS text,
+                           $4::float8 * freshness(timestamp, $5) AS freshness,
+                           -$6::float8 * ln(1 + number_js) AS js,
+                           -$7::float8 * url_depth(url) AS depth,
+                           $8::float8 * coalesce(ln(1 + inbound_links), 0) AS authority
+                    FROM (
+                        SELECT oid::regconfig AS config, websearch_to_tsquery(oid::regconfig, $1) AS terms FROM pg_ts_config
+                    ) AS queries
+                    JOIN webpages ON search_config = queries.config AND search_vector @@ terms
+                    WHERE $2::text IS NULL OR lang = $2
+                ) AS components
+                WHERE text + freshness + js + depth + authority >= $9
+                ORDER BY text + freshness + js + depth + authority DESC"#,
+                query.terms, query.lang, r.text_weight, r.freshness_weight, r.freshness_half_life_days,
+                r.js_penalty, r.url_depth_penalty, r.authority_weight, r.min_score
+                ).fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(SearchResult::from).collect())
+    }
+
+    /// Search for pages with words starting with each word of a query, or with a title or URL
+    /// containing something like it
+    ///
+    /// The text score is whichever of those matches best, and as the matches are looser there's
+    /// no minimum score.
+    async fn fuzzy_search(&self, query: &ParsedQuery) -> Result<Vec<SearchResult>, QueryError> {
+        let prefixes = prefix_tsquery(&query.terms);
+        let r = &self.ranking;
+        let rows = sqlx::query_as!(RankedRow, r#"
+                SELECT title, url, blurb, number_js, timestamp, config AS "config!",
+                       rank_a AS "rank_a!", rank_b AS "rank_b!", rank_c AS "rank_c!", rank_d AS "rank_d!",
+                       text AS "text!", freshness AS "freshness!", js AS "js!", depth AS "depth!", authority AS "authority!",
+                       text + freshness + js + depth + authority AS "total!"
+                FROM (
+                    SELECT title, url, blurb, number_js, timestamp, search_config::text AS config,
+                           ts_rank('{0, 0, 0, 1}', search_vector, to_tsquery('simple', $1))::float8 AS rank_a,
+                           ts_rank('{0, 0, 0.4, 0}', search_vector, to_tsquery('simple', $1))::float8 AS rank_b,
+                           ts_rank('{0, 0.2, 0, 0}', search_vector, to_tsquery('simple', $1))::float8 AS rank_c,
+                           ts_rank('{0.1, 0, 0, 0}', search_vector, to_tsquery('simple', $1))::float8 AS rank_d,
+                           $5::float8 * greatest(ts_rank(search_vector, to_tsquery('simple', $1)),
+                                                 word_similarity($2, title), word_similarity($2, url)) AS text,
+                           $6::float8 * freshness(timestamp, $7) AS freshness,
+                           -$8::float8 * ln(1 + number_js) AS js,
+                           -$9::float8 * url_depth(url) AS depth,
+                           $10::float8 * coalesce(ln(1 + inbound_links), 0) AS authority
+                    FROM webpages
+                    WHERE (($1 <> '' AND search_vector @@ to_tsquery('simple', $1)) OR $2 <% title OR $2 <% url)
+                      AND ($4::text IS NULL OR lang = $4)
+                ) AS components
+                ORDER BY text + freshness + js + depth + authority DESC
+                LIMIT $3"#,
+                prefixes, query.terms, MAX_FUZZY_RESULTS, query.lang, r.text_weight, r.freshness_weight,
+                r.freshness_half_life_days, r.js_penalty, r.url_depth_penalty, r.authority_weight
+                ).fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(SearchResult::from).collect())
+    }
+
+    /// How a query is parsed and turned into a `tsquery` for each text search configuration
+    /// pages are indexed with
+    ///
+    /// `mode` should be the one the results came from (see `SearchResponse::mode`), as `auto` is
+    /// explained as a standard search.
+    pub async fn explain_query(&self, input: &str, mode: SearchMode) -> Result<QueryExplanation, QueryError> {
+        let parsed = ParsedQuery::parse(input.trim())?;
+        let tsquery = match mode {
+            SearchMode::Standard | SearchMode::Auto => sqlx::query!(r#"
+                    SELECT config::text AS "config!", websearch_to_tsquery(config, $1)::text AS "tsquery!"
+                    FROM (SELECT DISTINCT search_config AS config FROM webpages WHERE $2::text IS NULL OR lang = $2) AS configs"#,
+                    parsed.terms, parsed.lang)
+                .fetch_all(&self.pool).await?
+                .into_iter().map(|row| (row.config, row.tsquery)).collect(),
+            SearchMode::Fuzzy => BTreeMap::from([(String::from("simple"), prefix_tsquery(&parsed.terms))]),
         };
+        Ok(QueryExplanation { parsed, mode, tsquery, weights: self.ranking.clone() })
+    }
 
-        match sqlx::query!(r#"INSERT INTO webpages (title, url, blurb, content, number_js) VALUES ($1, $2, $3, $4, $5)"#, title, url, blurb, content, number_js).execute(&self.pool).await {
-            Ok(_) => { info!("Added {title}, {url} to database successfully!"); },
-            Err(_) => warn!("Couldn't add to database!")
+    /// Search, suggesting a spelling correction if there are few results (see `did_you_mean`)
+    pub async fn search_with_suggestion(&self, input: &str, mode: SearchMode) -> Result<SearchResponse, QueryError> {
+        let (results, used_mode) = self.search(input, mode).await?;
+        let mut suggestion = None;
+        if results.len() < SPARSE_RESULTS {
+            let query = ParsedQuery::parse(input)?;
+            if let Some(corrected) = self.did_you_mean(&query.terms).await? {
+                let corrected = query.with_terms(&corrected);
+                // Only worth suggesting if it finds more
+                if self.search(&corrected, mode).await?.0.len() > results.len() { suggestion = Some(corrected) }
+            }
         }
+        Ok(SearchResponse { query: input.trim().to_string(), results, suggestion, mode: used_mode, debug: None })
+    }
+
+    /// The query with any words that aren't in the index replaced by the most similar word that
+    /// is, or `None` if every word is there or has nothing similar
+    ///
+    /// The words come from `search_vocabulary`, so new pages are only known once `refresh_terms`
+    /// has run, as it does after crawls, imports and admin page changes.
+    pub async fn did_you_mean(&self, input: &str) -> Result<Option<String>, StorageError> {
+        let tokens: Vec<&str> = input.split_whitespace().collect();
+        // The word in each token, without quotes, a leading `-` or other punctuation
+        let words: Vec<String> = tokens.iter()
+            .map(|token| token.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+            .collect();
+        let corrections = sqlx::query!(r#"
+                SELECT typed.word AS "word!", coalesce(known.word, best.word) AS correction
+                FROM unnest($1::text[]) WITH ORDINALITY AS typed (word, n)
+                LEFT JOIN search_vocabulary AS known ON known.word = typed.word
+                LEFT JOIN LATERAL (
+                    SELECT word FROM search_vocabulary
+                    WHERE known.word IS NULL AND word % typed.word
+                    ORDER BY similarity(word, typed.word) DESC, ndoc DESC LIMIT 1
+                ) AS best ON true
+                ORDER BY typed.n"#, &words).fetch_all(&self.pool).await?;
+
+        let mut changed = false;
+        let corrected: Vec<String> = tokens.iter().zip(corrections).map(|(token, row)| match row.correction {
+            Some(correction) if !row.word.is_empty() && correction != row.word => {
+                changed = true;
+                token.to_lowercase().replacen(&row.word, &correction, 1)
+            },
+            _ => token.to_string(),
+        }).collect();
+        Ok(changed.then(|| corrected.join(" ")))
     }
 
-    pub async fn get_webpage(&self, url: String) -> Option<SearchResult>{
+    /// Count a search that found something, so it can be suggested to others
+    pub async fn log_query(&self, input: &str) -> Result<(), StorageError> {
+        let query = normalise_query(input);
+        if query.is_empty() || query.chars().count() > MAX_QUERY_LENGTH { return Ok(()) }
+        sqlx::query!(r#"
+                INSERT INTO search_queries (query) VALUES ($1)
+                ON CONFLICT (query) DO UPDATE SET count = search_queries.count + 1, last_searched = now()"#, query)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Completions for a partly typed query, from popular past searches, then page titles, then
+    /// terms in the index completing its last word
+    pub async fn suggest(&self, input: &str) -> Result<Vec<String>, StorageError> {
+        let query = normalise_query(input);
+        if query.is_empty() || query.chars().count() > MAX_QUERY_LENGTH { return Ok(Vec::new()) }
+        let limit = MAX_SUGGESTIONS as i64;
+        let prefix = format!("{}%", escape_like(&query));
+
+        let mut suggestions = sqlx::query_scalar!(r#"
+                SELECT query FROM search_queries WHERE query LIKE $1
+                ORDER BY count DESC, last_searched DESC LIMIT $2"#, prefix, limit)
+            .fetch_all(&self.pool).await?;
+        suggestions.extend(sqlx::query_scalar!(r#"
+                SELECT lower(title) AS "title!" FROM webpages WHERE lower(title) LIKE $1
+                GROUP BY lower(title) ORDER BY count(*) DESC, lower(title) LIMIT $2"#, prefix, limit)
+            .fetch_all(&self.pool).await?);
+
+        // Complete the word being typed, unless it's been finished with a space
+        if !input.ends_with(char::is_whitespace) {
+            let (head, last) = match query.rsplit_once(' ') {
+                Some((head, last)) => (format!("{head} "), last),
+                None => (String::new(), query.as_str()),
+            };
+            let terms = sqlx::query_scalar!(r#"
+                    SELECT word AS "word!" FROM search_terms WHERE word LIKE $1
+                    ORDER BY ndoc DESC, word LIMIT $2"#, format!("{}%", escape_like(last)), limit)
+                .fetch_all(&self.pool).await?;
+            suggestions.extend(terms.into_iter().map(|term| format!("{head}{term}")));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        suggestions.retain(|suggestion| seen.insert(suggestion.clone()));
+        suggestions.truncate(MAX_SUGGESTIONS);
+        Ok(suggestions)
+    }
+
+    /// Rebuild the dictionaries of indexed terms and words that suggestions and spelling
+    /// corrections are drawn from
+    pub async fn refresh_terms(&self) -> Result<(), StorageError> {
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY search_terms").execute(&self.pool).await?;
+        sqlx::query!("REFRESH MATERIALIZED VIEW CONCURRENTLY search_vocabulary").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Adds a webpage to the database, or updates it if its url is already there
+    ///
+    /// The row keeps its id when updated. If the title, blurb, content, number of scripts and
+    /// language are all the same the row is left alone, unless `force` is set, in which case its
+    /// timestamp is still refreshed. The page is indexed with the text search configuration for
+    /// its language, or `simple` if there isn't one.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_webpage(&self, title: String, url: String, blurb: String, content: String, number_js: i32, lang: Option<String>, force: bool) -> Result<Upsert, StorageError> {
+        debug!("Adding {url} to database...");
+
+        let config = crate::lang::config_for(lang.as_deref());
+        let res = sqlx::query!(r#"
+                WITH old AS (
+                    SELECT id, title, blurb, content, number_js, lang FROM webpages WHERE url = $2
+                ), upserted AS (
+                    INSERT INTO webpages (title, url, blurb, content, number_js, lang, search_config)
+                    VALUES ($1, $2, $3, $4, $5, $7, coalesce((SELECT oid::regconfig FROM pg_ts_config WHERE cfgname = $8), 'simple'))
+                    ON CONFLICT (url) DO UPDATE
+                    SET title = EXCLUDED.title, blurb = EXCLUDED.blurb, content = EXCLUDED.content,
+                        number_js = EXCLUDED.number_js, lang = EXCLUDED.lang,
+                        search_config = EXCLUDED.search_config, timestamp = now()
+                    WHERE $6 OR (webpages.title, webpages.blurb, webpages.content, webpages.number_js, webpages.lang)
+                        IS DISTINCT FROM (EXCLUDED.title, EXCLUDED.blurb, EXCLUDED.content, EXCLUDED.number_js, EXCLUDED.lang)
+                    RETURNING id, xmax = 0 AS inserted
+                )
+                SELECT coalesce(upserted.id, old.id) AS id,
+                       coalesce(upserted.inserted, false) AS "inserted!",
+                       (old.title, old.blurb, old.content, old.number_js, old.lang)
+                           IS NOT DISTINCT FROM ($1, $3, $4, $5, $7) AS "unchanged!"
+                FROM (SELECT 1) AS one
+                LEFT JOIN upserted ON true
+                LEFT JOIN old ON true"#,
+                title, url, blurb, content, number_js, force, lang, config).fetch_one(&self.pool).await?;
+
+        let upsert = match res.id {
+            Some(id) if res.inserted => Upsert::Inserted(id),
+            Some(id) if res.unchanged => Upsert::Unchanged(id),
+            Some(id) => Upsert::Updated(id),
+            None => return Err(StorageError::Conflict { url }),
+        };
+
+        match upsert {
+            Upsert::Inserted(_) => info!("Added {title}, {url} to database successfully!"),
+            Upsert::Updated(_) => info!("Updated {title}, {url} in database successfully!"),
+            Upsert::Unchanged(_) => info!("{url} already in database and unchanged"),
+        }
+        Ok(upsert)
+    }
+
+    /// Get a webpage from the database by its url, if it's there
+    pub async fn get_webpage(&self, url: String) -> Result<Option<SearchResult>, StorageError> {
         debug!("Getting {url} from database...");
 
-        match sqlx::query_as!(SearchResult, r#" 
+        let row = sqlx::query!(r#" 
                 SELECT title, url, blurb, number_js, ts_rank(search_vector, websearch_to_tsquery($1)) AS rank, timestamp
                 FROM webpages
-                WHERE url = $1"# , url).fetch_one(&self.pool).await {
-            Ok(res) => Some(res),
-            Err(_) => None
-        }
+                WHERE url = $1"# , url).fetch_optional(&self.pool).await?;
+        Ok(row.map(|row| SearchResult {
+            title: row.title, url: row.url, blurb: row.blurb, number_js: row.number_js, rank: row.rank,
+            timestamp: row.timestamp, score: None,
+        }))
     }
 
-    async fn drop_index(&self, url: String) {
-        match sqlx::query!("DELETE FROM webpages WHERE url = $1", url).execute(&self.pool).await {
-            Ok(_) => debug!("Deleted index {url}..."),
-            Err(_) => warn!("Couldn't delete index with {url}!")
+    /// Set when a page was indexed, such as to keep the time from an export
+    pub async fn set_timestamp(&self, id: i32, timestamp: chrono::DateTime<Utc>) -> Result<(), StorageError> {
+        sqlx::query!("UPDATE webpages SET timestamp = $2 WHERE id = $1", id, timestamp)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Add more links found to pages by a crawl to its counts, which can be done in batches as
+    /// the crawl goes on
+    ///
+    /// `inbound_links` keeps the most links any one crawl has found, as a crawl that stopped early
+    /// or only covered part of a site won't have seen every link.
+    pub async fn add_inbound_links(&self, crawl_id: i64, found_links: &HashMap<String, u32>) -> Result<(), StorageError> {
+        let (urls, counts): (Vec<String>, Vec<i32>) = found_links.iter()
+            .map(|(url, count)| (url.clone(), i32::try_from(*count).unwrap_or(i32::MAX)))
+            .unzip();
+        sqlx::query!(r#"
+                UPDATE webpages SET inbound_links = greatest(inbound_links, found.total),
+                                    crawl_inbound_links = found.total, inbound_links_crawl_id = $1
+                FROM (
+                    SELECT webpages.id, CASE WHEN inbound_links_crawl_id = $1 THEN crawl_inbound_links ELSE 0 END + links AS total
+                    FROM unnest($2::text[], $3::int[]) AS links (url, links)
+                    JOIN webpages USING (url)
+                ) AS found
+                WHERE webpages.id = found.id"#,
+                crawl_id, &urls, &counts).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Add a page from an export, keeping when it was crawled rather than when it was imported
+    ///
+    /// With `force` even an unchanged page is rewritten, so its timestamp is put back too.
+    pub async fn import_page(&self, page: ExportedPage, force: bool) -> Result<Upsert, StorageError> {
+        let ExportedPage { url, title, blurb, content, number_js, lang, timestamp } = page;
+        let upsert = self.add_webpage(title, url, blurb.unwrap_or_default(), content, number_js, lang, force).await?;
+        match upsert {
+            Upsert::Inserted(id) | Upsert::Updated(id) => self.set_timestamp(id, timestamp).await?,
+            Upsert::Unchanged(id) if force => self.set_timestamp(id, timestamp).await?,
+            Upsert::Unchanged(_) => {},
         }
+        Ok(upsert)
+    }
+
+    /// Remove a webpage from the index, returning false if it wasn't there
+    pub async fn delete_webpage(&self, url: &str) -> Result<bool, StorageError> {
+        let result = sqlx::query!("DELETE FROM webpages WHERE url = $1", url).execute(&self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// When each of a list of URLs was last indexed, for those that have been
+    pub async fn last_indexed(&self, urls: Vec<String>) -> Result<HashMap<String, chrono::DateTime<Utc>>, StorageError> {
+        if urls.is_empty() { return Ok(HashMap::new()) }
+        Ok(sqlx::query!(r#"SELECT url, timestamp FROM webpages WHERE url = ANY($1)"#, &urls)
+            .fetch_all(&self.pool).await?
+            .into_iter()
+            .map(|row| (row.url, row.timestamp))
+            .collect())
+    }
+
+    /// Get summary statistics about the index
+    pub async fn stats(&self) -> Result<IndexStats, StorageError> {
+        Ok(sqlx::query_as!(IndexStats, r#"
+                SELECT count(*) AS "pages!",
+                       count(DISTINCT substring(url from '^[a-z]+://([^/:?#]+)')) AS "hosts!",
+                       coalesce(sum(number_js), 0) AS "number_js!",
+                       min(timestamp) AS oldest,
+                       max(timestamp) AS newest
+                FROM webpages"#).fetch_one(&self.pool).await?)
+    }
+
+    /// Stream every webpage in the index, in the order they were added
+    pub fn export_pages(&self) -> BoxStream<'_, Result<ExportedPage, StorageError>> {
+        sqlx::query_as!(ExportedPage, r#"
+                SELECT url, title, blurb, content, number_js, lang, timestamp
+                FROM webpages
+                ORDER BY id"#).fetch(&self.pool).map_err(StorageError::from).boxed()
+    }
+
+    /// Add a site to the moderation queue, unless it's already waiting for review or the
+    /// submitter has sent `per_hour` sites in the last hour
+    pub async fn add_submission(&self, url: &str, note: Option<&str>, submitter: &str, per_hour: u32) -> Result<Submitted, StorageError> {
+        let mut tx = self.pool.begin().await?;
+        // Sites from one submitter are added one at a time, so requests sent together can't all
+        // count the same earlier submissions and get past the limit
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))").bind(submitter).execute(&mut *tx).await?;
+        let recent = sqlx::query_scalar!(r#"
+                SELECT count(*) AS "count!" FROM submissions
+                WHERE submitter = $1 AND submitted_at > now() - interval '1 hour'"#, submitter)
+            .fetch_one(&mut *tx).await?;
+        if recent >= i64::from(per_hour) { return Ok(Submitted::RateLimited) }
+
+        let id = sqlx::query_scalar!(r#"
+                INSERT INTO submissions (url, note, submitter) VALUES ($1, $2, $3)
+                ON CONFLICT (url) WHERE status = 'pending' DO NOTHING
+                RETURNING id"#, url, note, submitter).fetch_optional(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(id.map_or(Submitted::Duplicate, Submitted::Queued))
+    }
+
+    /// Submitted sites, oldest first, optionally only those with a status
+    pub async fn submissions(&self, status: Option<SubmissionStatus>, limit: i64) -> Result<Vec<Submission>, StorageError> {
+        Ok(sqlx::query_as!(Submission, r#"
+                SELECT id, url, note, submitter, submitted_at, status, reviewed_at FROM submissions
+                WHERE status = $1 OR $1 IS NULL
+                ORDER BY submitted_at LIMIT $2"#, status.map(|s| s.as_str()), limit)
+            .fetch_all(&self.pool).await?)
+    }
+
+    /// Approve or reject a pending submission, returning it if it was pending
+    pub async fn review_submission(&self, id: i64, status: SubmissionStatus) -> Result<Option<Submission>, StorageError> {
+        Ok(sqlx::query_as!(Submission, r#"
+                UPDATE submissions SET status = $2, reviewed_at = now()
+                WHERE id = $1 AND status = 'pending'
+                RETURNING id, url, note, submitter, submitted_at, status, reviewed_at"#, id, status.as_str())
+            .fetch_optional(&self.pool).await?)
+    }
+
+    /// Record the start of a crawl, returning its id for the fetch log
+    pub async fn start_crawl(&self) -> Result<i64, StorageError> {
+        Ok(sqlx::query_scalar!("INSERT INTO crawls DEFAULT VALUES RETURNING id").fetch_one(&self.pool).await?)
+    }
+
+    /// Record the end of a crawl
+    pub async fn finish_crawl(&self, crawl_id: i64) -> Result<(), StorageError> {
+        sqlx::query!("UPDATE crawls SET finished_at = now() WHERE id = $1", crawl_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Add a fetch attempt to the fetch log
+    pub async fn log_fetch(&self, crawl_id: Option<i64>, record: &FetchRecord) -> Result<(), StorageError> {
+        sqlx::query!(r#"
+                INSERT INTO fetch_log (crawl_id, url, host, status, bytes, latency_ms, outcome, error, kind)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+                crawl_id, record.url, record.host,
+                record.status.map(|s| s as i16),
+                record.bytes.map(|b| i32::try_from(b).unwrap_or(i32::MAX)),
+                record.latency.map(|l| i32::try_from(l.as_millis()).unwrap_or(i32::MAX)),
+                record.outcome.as_str(), record.error, record.kind.as_str()
+                ).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Summarise a crawl from the fetch log, or the latest crawl if no id is given, with up to
+    /// `limit` rows in each section
+    ///
+    /// Only pages count towards the report, not `robots.txt` and sitemaps, which are often missing
+    /// without anything being wrong and are fetched differently.
+    pub async fn crawl_report(&self, crawl_id: Option<i64>, limit: i64) -> Result<Option<CrawlReport>, StorageError> {
+        let crawl = sqlx::query_as!(CrawlSummary, r#"
+                SELECT id, started_at, finished_at FROM crawls
+                WHERE id = $1 OR $1 IS NULL
+                ORDER BY id DESC LIMIT 1"#, crawl_id).fetch_optional(&self.pool).await?;
+        let crawl = match crawl {
+            Some(crawl) => crawl,
+            None => return Ok(None),
+        };
+
+        let outcomes = sqlx::query_as!(OutcomeCount, r#"
+                SELECT outcome, count(*) AS "count!" FROM fetch_log
+                WHERE crawl_id = $1 AND kind = 'page'
+                GROUP BY outcome ORDER BY count(*) DESC"#, crawl.id).fetch_all(&self.pool).await?;
+
+        let error_hosts = sqlx::query_as!(HostErrors, r#"
+                SELECT host, count(*) AS "fetches!",
+                       count(*) FILTER (WHERE outcome IN ('http_error', 'timeout', 'error')) AS "errors!",
+                       (count(*) FILTER (WHERE outcome IN ('http_error', 'timeout', 'error')))::float8 / count(*) AS "error_rate!"
+                FROM fetch_log
+                WHERE crawl_id = $1 AND kind = 'page' AND latency_ms IS NOT NULL
+                GROUP BY host
+                HAVING count(*) FILTER (WHERE outcome IN ('http_error', 'timeout', 'error')) > 0
+                ORDER BY 4 DESC, 3 DESC LIMIT $2"#, crawl.id, limit).fetch_all(&self.pool).await?;
+
+        let slow_hosts = sqlx::query_as!(HostLatency, r#"
+                SELECT host, count(*) AS "fetches!", avg(latency_ms)::float8 AS "avg_ms!", max(latency_ms) AS "max_ms!"
+                FROM fetch_log
+                WHERE crawl_id = $1 AND kind = 'page' AND latency_ms IS NOT NULL
+                GROUP BY host
+                ORDER BY 3 DESC LIMIT $2"#, crawl.id, limit).fetch_all(&self.pool).await?;
+
+        let failing_paths = sqlx::query_as!(FailingPath, r#"
+                SELECT coalesce(nullif(regexp_replace(url, '^[a-z]+://[^/]+', ''), ''), '/') AS "path!",
+                       count(*) AS "failures!", count(DISTINCT host) AS "hosts!"
+                FROM fetch_log
+                WHERE crawl_id = $1 AND kind = 'page' AND outcome IN ('http_error', 'timeout', 'error')
+                GROUP BY 1
+                ORDER BY 2 DESC, 1 LIMIT $2"#, crawl.id, limit).fetch_all(&self.pool).await?;
+
+        Ok(Some(CrawlReport { crawl, outcomes, error_hosts, slow_hosts, failing_paths }))
     }
 }
 
-/// Small struct used in checking if pages are already present in the database before adding
-/// them
-struct TCU {
-    title: String,
-    content: String,
-    url: String
+/// Lower case a query and collapse its whitespace, so the same search is always logged the same
+fn normalise_query(input: &str) -> String {
+    input.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Escape the wildcards in text to match literally with `LIKE`
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// A `tsquery` matching words starting with each word of the terms
+///
+/// Only letters and digits are kept, so the words can't be read as tsquery operators.
+fn prefix_tsquery(terms: &str) -> String {
+    terms.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_PREFIX_LENGTH)
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(" & ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(pool: Pool<Postgres>) -> DB {
+        DB { pool, ranking: RankingConfig::default() }
+    }
+
+    #[test]
+    fn queries_are_logged_the_same_however_they_are_typed() {
+        assert_eq!(normalise_query("  Rust\tWEB   Crawler \n"), "rust web crawler");
+        assert_eq!(normalise_query("ÜBER Straße"), "über straße");
+        assert_eq!(normalise_query("\"Full Text\" -MySQL"), "\"full text\" -mysql");
+        assert_eq!(normalise_query("   "), "");
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("snake_case"), "snake\\_case");
+        assert_eq!(escape_like("C:\\dir"), "C:\\\\dir");
+        assert_eq!(escape_like("\\%_"), "\\\\\\%\\_");
+        assert_eq!(escape_like("it's \"plain\" ünïcode"), "it's \"plain\" ünïcode");
+    }
+
+    #[test]
+    fn prefix_queries_keep_only_words() {
+        assert_eq!(prefix_tsquery("Rust crawl"), "rust:* & crawl:*");
+        assert_eq!(prefix_tsquery("a bc d"), "bc:*");
+        assert_eq!(prefix_tsquery("snake_case kebab-case"), "snake:* & case:* & kebab:* & case:*");
+        assert_eq!(prefix_tsquery("ÜBER straße 東京"), "über:* & straße:* & 東京:*");
+        assert_eq!(prefix_tsquery("it's \"quoted\""), "it:* & quoted:*");
+        assert_eq!(prefix_tsquery("a & b | !c : * ( ) <-> '' \\"), "");
+        assert_eq!(prefix_tsquery("rust&!(web|crawler):*"), "rust:* & web:* & crawler:*");
+    }
+
+    #[sqlx::test]
+    async fn prefix_queries_are_valid_tsqueries(pool: Pool<Postgres>) {
+        for terms in ["Rust crawl", "it's \"quoted\"", "rust&!(web|crawler):*", "a & b | !c : * ( ) <-> '' \\", ""] {
+            let tsquery: String = sqlx::query_scalar("SELECT to_tsquery('simple', $1)::text").bind(prefix_tsquery(terms))
+                .fetch_one(&pool).await.unwrap();
+            assert!(!tsquery.contains(['|', '!', '(', '<']), "{terms:?} became {tsquery:?}");
+        }
+    }
+
+    #[sqlx::test]
+    async fn escaped_like_patterns_match_literally(pool: Pool<Postgres>) {
+        for (text, prefix, matches) in [
+            ("100% cotton", "100%", true), ("1000 cotton", "100%", false),
+            ("snake_case", "snake_", true), ("snakescase", "snake_", false),
+            ("c:\\dir", "c:\\", true), ("c:dir", "c:\\", false),
+            ("über", "üb", true),
+        ] {
+            let matched: bool = sqlx::query_scalar("SELECT $1 LIKE $2").bind(text).bind(format!("{}%", escape_like(prefix)))
+                .fetch_one(&pool).await.unwrap();
+            assert_eq!(matched, matches, "{text:?} LIKE {prefix:?}");
+        }
+    }
+
+    async fn export(db: &DB) -> Vec<ExportedPage> {
+        db.export_pages().try_collect().await.unwrap()
+    }
+
+    /// Add a few pages crawled a while ago, returning them as exported
+    async fn add_old_pages(db: &DB) -> Vec<ExportedPage> {
+        let long_ago = Utc::now() - ::chrono::Duration::days(30);
+        for (n, url) in ["https://example.com/", "https://example.com/a"].into_iter().enumerate() {
+            let upsert = db.add_webpage(format!("Page {n}"), url.to_string(), String::new(),
+                                        String::from("Some words"), 0, None, false).await.unwrap();
+            let (Upsert::Inserted(id) | Upsert::Updated(id) | Upsert::Unchanged(id)) = upsert;
+            db.set_timestamp(id, long_ago - ::chrono::Duration::hours(n as i64)).await.unwrap();
+        }
+        export(db).await
+    }
+
+    fn assert_same_timestamps(before: &[ExportedPage], after: &[ExportedPage]) {
+        assert_eq!(before.len(), after.len());
+        for (before, after) in before.iter().zip(after) {
+            assert_eq!(before.url, after.url);
+            assert_eq!(before.timestamp, after.timestamp);
+        }
+    }
+
+    #[sqlx::test]
+    async fn import_keeps_exported_timestamps(pool: Pool<Postgres>) {
+        let db = db(pool);
+        let exported = add_old_pages(&db).await;
+        sqlx::query("DELETE FROM webpages").execute(&db.pool).await.unwrap();
+
+        for page in exported.clone() {
+            assert!(matches!(db.import_page(page, false).await.unwrap(), Upsert::Inserted(_)));
+        }
+        assert_same_timestamps(&exported, &export(&db).await);
+    }
+
+    #[sqlx::test]
+    async fn forced_import_keeps_exported_timestamps(pool: Pool<Postgres>) {
+        let db = db(pool);
+        let exported = add_old_pages(&db).await;
+
+        for page in exported.clone() {
+            assert!(matches!(db.import_page(page, true).await.unwrap(), Upsert::Unchanged(_)));
+        }
+        assert_same_timestamps(&exported, &export(&db).await);
+    }
+
+    async fn inbound_links(db: &DB, url: &str) -> Option<i32> {
+        sqlx::query_scalar("SELECT inbound_links FROM webpages WHERE url = $1").bind(url).fetch_one(&db.pool).await.unwrap()
+    }
+
+    #[sqlx::test]
+    async fn inbound_links_add_up_within_a_crawl_and_keep_the_most_across_crawls(pool: Pool<Postgres>) {
+        let db = db(pool);
+        add_old_pages(&db).await;
+        let url = "https://example.com/a";
+        let found = |links: u32| HashMap::from([(url.to_string(), links)]);
+
+        let first = db.start_crawl().await.unwrap();
+        db.add_inbound_links(first, &found(2)).await.unwrap();
+        db.add_inbound_links(first, &found(3)).await.unwrap();
+        assert_eq!(inbound_links(&db, url).await, Some(5));
+
+        // A smaller crawl doesn't lower the count, until it's found more
+        let second = db.start_crawl().await.unwrap();
+        db.add_inbound_links(second, &found(4)).await.unwrap();
+        assert_eq!(inbound_links(&db, url).await, Some(5));
+        db.add_inbound_links(second, &found(2)).await.unwrap();
+        assert_eq!(inbound_links(&db, url).await, Some(6));
+
+        // Links to pages that aren't indexed are ignored
+        db.add_inbound_links(second, &HashMap::from([(String::from("https://example.com/b"), 1)])).await.unwrap();
+        assert_eq!(inbound_links(&db, "https://example.com/").await, None);
+    }
+
+    #[sqlx::test]
+    async fn submissions_sent_together_stay_within_the_limit(pool: Pool<Postgres>) {
+        let db = db(pool);
+        let tasks: Vec<_> = (0..10).map(|n| {
+            let db = db.clone();
+            tokio::spawn(async move { db.add_submission(&format!("https://example{n}.com/"), None, "1.2.3.4", 3).await.unwrap() })
+        }).collect();
+        let mut queued = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Submitted::Queued(_) => queued += 1,
+                Submitted::RateLimited => {},
+                Submitted::Duplicate => panic!("the URLs are all different"),
+            }
+        }
+        assert_eq!(queued, 3);
+
+        // Other submitters have their own limit, and a site can only wait for review once
+        assert!(matches!(db.add_submission("https://other.com/", None, "5.6.7.8", 3).await.unwrap(), Submitted::Queued(_)));
+        assert!(matches!(db.add_submission("https://other.com/", None, "5.6.7.8", 3).await.unwrap(), Submitted::Duplicate));
+    }
+
+    #[sqlx::test]
+    async fn reports_only_count_page_fetches(pool: Pool<Postgres>) {
+        let db = db(pool);
+        let crawl_id = db.start_crawl().await.unwrap();
+        let fetch = |url: &str, kind, outcome, latency_ms| FetchRecord {
+            url: url.to_string(),
+            host: String::from("example.com"),
+            status: Some(if outcome == FetchOutcome::Ok { 200 } else { 404 }),
+            bytes: Some(100),
+            latency: Some(std::time::Duration::from_millis(latency_ms)),
+            kind,
+            outcome,
+            error: None,
+        };
+        db.log_fetch(Some(crawl_id), &fetch("https://example.com/", FetchKind::Page, FetchOutcome::Ok, 100)).await.unwrap();
+        db.log_fetch(Some(crawl_id), &fetch("https://example.com/robots.txt", FetchKind::Robots, FetchOutcome::HttpError, 5000)).await.unwrap();
+        db.log_fetch(Some(crawl_id), &fetch("https://example.com/sitemap.xml", FetchKind::Sitemap, FetchOutcome::HttpError, 3000)).await.unwrap();
+
+        let report = db.crawl_report(Some(crawl_id), 10).await.unwrap().unwrap();
+        assert_eq!(report.outcomes.len(), 1);
+        assert_eq!(report.outcomes[0].count, 1);
+        assert!(report.error_hosts.is_empty());
+        assert!(report.failing_paths.is_empty());
+        assert_eq!(report.slow_hosts.len(), 1);
+        assert_eq!(report.slow_hosts[0].fetches, 1);
+        assert_eq!(report.slow_hosts[0].max_ms, 100);
+    }
 }
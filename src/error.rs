@@ -0,0 +1,173 @@
+use thiserror::Error;
+
+/// Errors from fetching a document over HTTP
+#[derive(Debug,Error)]
+pub enum FetchError {
+    /// The request couldn't be sent, or no response came back
+    #[error("request to {url} failed: {source}")]
+    Request { url: String, #[source] source: reqwest::Error },
+    /// The server responded with something other than 200 OK
+    #[error("{url} responded with HTTP {status}")]
+    Status { url: String, status: reqwest::StatusCode, retry_after: Option<std::time::Duration> },
+    /// The response was something other than an HTML document
+    #[error("{url} is {content_type}, not HTML")]
+    NotHtml { url: String, content_type: String },
+    /// The response body couldn't be read as text
+    #[error("couldn't read the body of {url}: {source}")]
+    Body { url: String, #[source] source: reqwest::Error },
+}
+
+impl FetchError {
+    /// Whether the failure might go away if the request is tried again later
+    pub fn is_transient(&self) -> bool {
+        match self {
+            FetchError::Request { .. } => true,
+            FetchError::Body { source, .. } => source.is_timeout(),
+            FetchError::Status { status, .. } => status.as_u16() == 429 || status.is_server_error(),
+            FetchError::NotHtml { .. } => false,
+        }
+    }
+
+    /// How long the server asked to wait before trying again, with a `Retry-After` header
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            FetchError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Errors from parsing a URL or a fetched document
+#[derive(Debug,Error)]
+pub enum ParseError {
+    /// A URL (or a link relative to one) isn't valid
+    #[error("invalid url {url}: {source}")]
+    Url { url: String, #[source] source: url::ParseError },
+    /// A URL is valid, but has no host to crawl
+    #[error("{url} has no host")]
+    NoHost { url: String },
+    /// The document couldn't be parsed as HTML
+    #[error("couldn't parse the HTML of {url}: {source}")]
+    Html { url: String, #[source] source: tl::ParseError },
+}
+
+/// An allow or deny rule that couldn't be parsed
+#[derive(Debug,Error)]
+#[error("invalid url rule {rule:?}: {reason}")]
+pub struct RuleError {
+    pub rule: String,
+    pub reason: String,
+}
+
+/// Why a crawler couldn't be set up from its settings
+#[derive(Debug,Error)]
+pub enum SetupError {
+    #[error(transparent)]
+    Rule(#[from] RuleError),
+    /// The HTTP client couldn't be created, such as when TLS can't be initialised
+    #[error("couldn't create the HTTP client: {0}")]
+    Client(#[from] reqwest::Error),
+}
+
+/// A seed that couldn't be read or parsed
+#[derive(Debug,Error)]
+#[error("invalid seed in {origin} line {line}: {reason}")]
+pub struct SeedError {
+    /// Where the seed came from, like a file name
+    pub origin: String,
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Errors from saving or loading a crawl checkpoint
+#[derive(Debug,Error)]
+pub enum CheckpointError {
+    #[error("couldn't access checkpoint {path}: {source}")]
+    Io { path: String, source: std::io::Error },
+    #[error("invalid checkpoint {path} line {line}: {source}")]
+    Parse { path: String, line: usize, source: serde_json::Error },
+}
+
+/// Errors from reading or writing the database
+#[derive(Debug,Error)]
+pub enum StorageError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("migration failed: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+    /// A row was changed by someone else in the middle of an upsert
+    #[error("{url} was changed concurrently")]
+    Conflict { url: String },
+}
+
+/// Errors from running a search
+#[derive(Debug,Error)]
+pub enum QueryError {
+    /// There were no search terms
+    #[error("the search query is empty")]
+    Empty,
+    /// The search terms were longer than allowed
+    #[error("the search query is longer than {max} characters")]
+    TooLong { max: usize },
+    /// The search mode isn't one of `standard`, `fuzzy` or `auto`
+    #[error("unknown search mode {mode:?}, expected standard, fuzzy or auto")]
+    UnknownMode { mode: String },
+    /// A `lang:` filter isn't a language code or name
+    #[error("unknown language {lang:?} in lang: filter")]
+    UnknownLanguage { lang: String },
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+impl From<sqlx::Error> for QueryError {
+    fn from(err: sqlx::Error) -> QueryError {
+        QueryError::Storage(err.into())
+    }
+}
+
+/// Errors from crawling a single page, which are logged and the page skipped
+#[derive(Debug,Error)]
+pub enum CrawlError {
+    #[error(transparent)]
+    Fetch(#[from] FetchError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    /// The host's `robots.txt` doesn't allow the URL
+    #[error("{url} is disallowed by robots.txt")]
+    RobotsDenied { url: String },
+}
+
+/// Errors from submitting a site through the submission form, shown to the submitter
+#[derive(Debug,Error)]
+pub enum SubmitError {
+    /// The URL or note isn't acceptable, with why
+    #[error("{0}")]
+    Invalid(String),
+    #[error("too many sites have been submitted from your address, please try again later")]
+    RateLimited,
+    #[error("that site is already waiting for review")]
+    Duplicate,
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+/// Errors from managing crawl jobs through the admin API
+#[derive(Debug,Error)]
+pub enum JobError {
+    #[error("there is no job {id}")]
+    NotFound { id: u64 },
+    #[error("job {id} is already running")]
+    AlreadyRunning { id: u64 },
+    #[error("job {id} is {status:?}")]
+    WrongStatus { id: u64, status: crate::jobs::JobStatus },
+    #[error("no seeds given or enqueued")]
+    NoSeeds,
+    #[error(transparent)]
+    Seed(#[from] SeedError),
+    #[error(transparent)]
+    Setup(#[from] SetupError),
+    #[error(transparent)]
+    Crawl(#[from] CrawlError),
+}
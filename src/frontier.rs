@@ -0,0 +1,467 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use log::debug;
+
+use crate::error::CheckpointError;
+use crate::seen::SeenSet;
+
+/// A URL waiting to be crawled
+#[derive(Clone,Debug,Serialize,Deserialize)]
+pub struct FrontierEntry {
+    /// The URL to crawl
+    pub url: String,
+    /// The host of the URL
+    pub host: String,
+    /// The amount of links followed from a seed to get here (0 for the seeds themselves)
+    pub depth: u32,
+    /// The max amount of hops allowed from this URL's seed, if it overrides the budget's
+    pub max_hops: Option<u32>,
+    /// The priority of the URL's seed
+    pub priority: i32,
+    /// The priority the host's sitemap gives the URL (0.0 to 1.0), if it lists it
+    pub sitemap_priority: Option<f32>,
+    /// How stale the indexed copy of the URL is, from 0.0 (just indexed) to 1.0 (due to be
+    /// recrawled, or never indexed)
+    pub staleness: f32,
+    /// The links found to the URL so far in this crawl
+    #[serde(default)]
+    pub inbound: u32,
+}
+
+impl FrontierEntry {
+    /// A seed, with nothing known about it yet
+    pub fn seed(url: String, host: String, max_hops: Option<u32>, priority: i32) -> FrontierEntry {
+        FrontierEntry { url, host, depth: 0, max_hops, priority, sitemap_priority: None, staleness: 1.0, inbound: 0 }
+    }
+}
+
+/// CrawlBudget - the limits on how much a crawl fetches, where `None` is no limit
+#[derive(Clone,Debug,Default)]
+pub struct CrawlBudget {
+    /// The max amount of pages to crawl overall
+    pub max_pages: Option<usize>,
+    /// The max amount of links to follow from a seed
+    pub max_hops: Option<u32>,
+    /// The max amount of pages to crawl from any one host
+    pub max_pages_per_host: Option<usize>,
+    /// The max amount of bytes to download overall
+    pub max_bytes: Option<u64>,
+}
+
+/// A URL put back on the frontier until a set time
+#[derive(Clone,Debug)]
+struct Deferred {
+    entry: FrontierEntry,
+    ready_at: Instant,
+}
+
+/// A queued URL's place in the heap
+///
+/// When a queued URL gets another inbound link it's pushed again with a new generation and a
+/// higher score, and the old place is skipped when it's popped.
+#[derive(Clone,Debug)]
+struct Scored {
+    score: f64,
+    /// The order URLs were pushed in, so ties are crawled first come first served
+    seq: u64,
+    url: String,
+    generation: u64,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Scored) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Scored) -> Ordering {
+        self.score.total_cmp(&other.score).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// A URL waiting in the heap
+#[derive(Clone,Debug)]
+struct Queued {
+    entry: FrontierEntry,
+    generation: u64,
+}
+
+/// Frontier - the queue of URLs to crawl, highest scoring first, which keeps the crawl within
+/// its budget
+///
+/// A URL's score is made up of (see `Frontier::score`):
+/// - its seed's priority
+/// - minus one for every hop from the seed, so seeds and their neighbours come first
+/// - the log of how many links to it have been found, and of how many other hosts link to its host
+/// - its sitemap priority, if any
+/// - how stale its indexed copy is
+///
+/// URLs too many hops from a seed, or on hosts which have used up their share of pages, are
+/// turned away when pushed (and dropped when popped, as a host may fill up while its URLs are
+/// queued). Once the total page or byte budget is used up the frontier is exhausted. Every URL
+/// queued is remembered, so a URL is never queued twice, even after it's been crawled.
+#[derive(Debug,Default)]
+pub struct Frontier {
+    heap: BinaryHeap<Scored>,
+    /// The URLs in the heap, by URL
+    queued: HashMap<String, Queued>,
+    deferred: Vec<Deferred>,
+    budget: CrawlBudget,
+    /// Every URL queued so far, crawled or not
+    seen: SeenSet,
+    /// The other hosts found linking to each host
+    host_referrers: HashMap<String, HashSet<String>>,
+    /// The amount of URLs pushed so far, for breaking ties
+    seq: u64,
+    /// The pages crawled so far from each host
+    host_pages: HashMap<String, usize>,
+    /// The pages crawled so far
+    pages: usize,
+    /// The bytes downloaded so far
+    bytes: u64,
+}
+
+impl Frontier {
+    /// Create an empty frontier with a budget, remembering the URLs queued in `seen`
+    pub fn new(budget: CrawlBudget, seen: SeenSet) -> Frontier {
+        Frontier { budget, seen, ..Default::default() }
+    }
+
+    /// Queue a URL to crawl, linked to from `from_host` (or `None` for seeds), returning false if
+    /// it's over budget or has been queued before
+    ///
+    /// A URL that's still queued is moved up, for having another inbound link.
+    pub fn push(&mut self, mut entry: FrontierEntry, from_host: Option<&str>) -> bool {
+        if let Some(from) = from_host.filter(|from| *from != entry.host) {
+            self.host_referrers.entry(entry.host.clone()).or_default().insert(from.to_string());
+        }
+        if entry.max_hops.or(self.budget.max_hops).is_some_and(|max| entry.depth > max) {
+            debug!("Not queueing {}, it's {} hops from a seed", entry.url, entry.depth);
+            return false;
+        }
+        if self.host_full(&entry.host) {
+            debug!("Not queueing {}, {} has used its share of pages", entry.url, entry.host);
+            return false;
+        }
+        if !self.seen.insert(&entry.url) {
+            if let Some(queued) = self.queued.get_mut(&entry.url) {
+                queued.entry.inbound += 1;
+                queued.generation += 1;
+                let (entry, generation) = (queued.entry.clone(), queued.generation);
+                self.enqueue(entry, generation);
+            }
+            return false
+        }
+
+        entry.inbound += u32::from(from_host.is_some());
+        self.enqueue(entry, 0);
+        true
+    }
+
+    /// Put a URL in the heap with its current score
+    fn enqueue(&mut self, entry: FrontierEntry, generation: u64) {
+        let score = self.score(&entry);
+        self.seq += 1;
+        self.heap.push(Scored { score, seq: self.seq, url: entry.url.clone(), generation });
+        self.queued.insert(entry.url.clone(), Queued { entry, generation });
+    }
+
+    /// How soon a URL should be crawled, higher first
+    pub fn score(&self, entry: &FrontierEntry) -> f64 {
+        let referrers = self.host_referrers.get(&entry.host).map_or(0, |r| r.len());
+        f64::from(entry.priority)
+            - f64::from(entry.depth)
+            + 0.5 * f64::from(entry.inbound).ln_1p()
+            + 0.5 * (referrers as f64).ln_1p()
+            + f64::from(entry.sitemap_priority.map_or(0.0, |p| p - 0.5))
+            + f64::from(entry.staleness) - 1.0
+    }
+
+    /// Take the highest scoring URL to crawl, skipping any on hosts that have filled up
+    ///
+    /// Deferred URLs that are ready are queued again first.
+    pub fn pop(&mut self) -> Option<FrontierEntry> {
+        let now = Instant::now();
+        let (ready, waiting): (Vec<_>, _) = std::mem::take(&mut self.deferred).into_iter()
+            .partition(|d| d.ready_at <= now);
+        self.deferred = waiting;
+        for deferred in ready { self.enqueue(deferred.entry, 0) }
+
+        while let Some(scored) = self.heap.pop() {
+            if self.queued.get(&scored.url).is_none_or(|q| q.generation != scored.generation) { continue }
+            let Some(Queued { entry, .. }) = self.queued.remove(&scored.url) else { continue };
+            if !self.host_full(&entry.host) { return Some(entry) }
+            debug!("Dropping {}, {} has used its share of pages", entry.url, entry.host);
+        }
+        None
+    }
+
+    /// Save the URLs still waiting to be crawled to a file, as JSON lines, returning how many
+    /// there were
+    ///
+    /// This empties the frontier.
+    pub fn checkpoint(&mut self, path: &Path) -> Result<usize, CheckpointError> {
+        let io_err = |source| CheckpointError::Io { path: path.display().to_string(), source };
+        let mut entries = Vec::new();
+        while let Some(scored) = self.heap.pop() {
+            if self.queued.get(&scored.url).is_some_and(|q| q.generation == scored.generation) {
+                if let Some(queued) = self.queued.remove(&scored.url) { entries.push(queued.entry) }
+            }
+        }
+        entries.extend(std::mem::take(&mut self.deferred).into_iter().map(|d| d.entry));
+
+        let mut out = BufWriter::new(File::create(path).map_err(io_err)?);
+        for entry in &entries {
+            serde_json::to_writer(&mut out, entry).map_err(|e| io_err(e.into()))?;
+            writeln!(out).map_err(io_err)?;
+        }
+        out.flush().map_err(io_err)?;
+        Ok(entries.len())
+    }
+
+    /// Read the URLs saved by `checkpoint`
+    pub fn read_checkpoint(path: &Path) -> Result<Vec<FrontierEntry>, CheckpointError> {
+        let text = fs::read_to_string(path)
+            .map_err(|source| CheckpointError::Io { path: path.display().to_string(), source })?;
+        text.lines().enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| serde_json::from_str(line)
+                .map_err(|source| CheckpointError::Parse { path: path.display().to_string(), line: n + 1, source }))
+            .collect()
+    }
+
+    /// Put a URL back on the frontier, to be crawled no sooner than `ready_at`
+    pub fn defer(&mut self, entry: FrontierEntry, ready_at: Instant) {
+        self.deferred.push(Deferred { entry, ready_at });
+    }
+
+    /// When the next deferred URL will be ready, if there are any
+    pub fn next_deferred(&self) -> Option<Instant> {
+        self.deferred.iter().map(|d| d.ready_at).min()
+    }
+
+    /// Count a crawled page against the budget
+    pub fn record_page(&mut self, host: &str, bytes: usize) {
+        *self.host_pages.entry(host.to_string()).or_default() += 1;
+        self.pages += 1;
+        self.bytes += bytes as u64;
+    }
+
+    /// If the crawl has used up its page or byte budget, the reason why
+    pub fn exhausted(&self) -> Option<String> {
+        if let Some(max) = self.budget.max_pages.filter(|max| self.pages >= *max) {
+            return Some(format!("page limit of {max} reached"));
+        }
+        if let Some(max) = self.budget.max_bytes.filter(|max| self.bytes >= *max) {
+            return Some(format!("byte limit of {max} reached"));
+        }
+        None
+    }
+
+    /// Whether a URL is waiting in the heap to be crawled
+    pub fn is_queued(&self, url: &str) -> bool {
+        self.queued.contains_key(url)
+    }
+
+    /// The amount of URLs queued, not counting deferred ones
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// The amount of pages crawled so far
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// The amount of bytes downloaded so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The amount of URLs ever queued
+    pub fn seen_len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// The amount of URLs deferred for later
+    pub fn deferred_len(&self) -> usize {
+        self.deferred.len()
+    }
+
+    /// Whether a host has had all the pages it's allowed
+    fn host_full(&self, host: &str) -> bool {
+        self.budget.max_pages_per_host
+            .is_some_and(|max| self.host_pages.get(host).copied().unwrap_or_default() >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, depth: u32) -> FrontierEntry {
+        let host = url.split('/').nth(2).unwrap_or_default().to_string();
+        FrontierEntry { depth, ..FrontierEntry::seed(url.to_string(), host, None, 0) }
+    }
+
+    fn frontier(budget: CrawlBudget) -> Frontier {
+        Frontier::new(budget, SeenSet::default())
+    }
+
+    #[test]
+    fn urls_too_many_hops_from_a_seed_are_turned_away() {
+        let mut frontier = frontier(CrawlBudget { max_hops: Some(1), ..Default::default() });
+        assert!(frontier.push(entry("https://a.com/", 0), None));
+        assert!(frontier.push(entry("https://a.com/1", 1), Some("a.com")));
+        assert!(!frontier.push(entry("https://a.com/2", 2), Some("a.com")));
+        // A seed's own limit overrides the budget's
+        assert!(frontier.push(FrontierEntry { max_hops: Some(2), ..entry("https://b.com/2", 2) }, Some("a.com")));
+        assert!(!frontier.push(FrontierEntry { max_hops: Some(0), ..entry("https://b.com/1", 1) }, Some("a.com")));
+        assert_eq!(frontier.len(), 3);
+    }
+
+    #[test]
+    fn hosts_stop_at_their_share_of_pages() {
+        let mut frontier = frontier(CrawlBudget { max_pages_per_host: Some(1), ..Default::default() });
+        for url in ["https://a.com/1", "https://a.com/2", "https://b.com/1"] {
+            assert!(frontier.push(entry(url, 0), None));
+        }
+        let first = frontier.pop().unwrap();
+        assert_eq!(first.url, "https://a.com/1");
+        frontier.record_page(&first.host, 10);
+        // The other page on the same host is dropped when popped, and no more are queued
+        assert!(!frontier.push(entry("https://a.com/3", 1), Some("b.com")));
+        let rest: Vec<_> = std::iter::from_fn(|| frontier.pop()).map(|e| e.url).collect();
+        assert_eq!(rest, vec!["https://b.com/1"]);
+    }
+
+    #[test]
+    fn page_and_byte_limits_exhaust_the_crawl() {
+        let mut frontier = frontier(CrawlBudget { max_pages: Some(2), ..Default::default() });
+        assert_eq!(frontier.exhausted(), None);
+        frontier.record_page("a.com", 100);
+        assert_eq!(frontier.exhausted(), None);
+        frontier.record_page("b.com", 100);
+        assert_eq!(frontier.exhausted().as_deref(), Some("page limit of 2 reached"));
+        assert_eq!((frontier.pages(), frontier.bytes()), (2, 200));
+
+        let mut frontier = self::frontier(CrawlBudget { max_bytes: Some(1000), ..Default::default() });
+        frontier.record_page("a.com", 999);
+        assert_eq!(frontier.exhausted(), None);
+        frontier.record_page("a.com", 1);
+        assert_eq!(frontier.exhausted().as_deref(), Some("byte limit of 1000 reached"));
+    }
+
+    #[test]
+    fn urls_are_only_queued_once() {
+        let mut frontier = frontier(CrawlBudget::default());
+        assert!(frontier.push(entry("https://a.com/", 0), None));
+        assert!(!frontier.push(entry("https://a.com/", 0), None));
+        assert_eq!(frontier.pop().unwrap().url, "https://a.com/");
+        // Even once it's been crawled
+        assert!(!frontier.push(entry("https://a.com/", 1), Some("b.com")));
+        assert!(frontier.pop().is_none());
+        assert_eq!(frontier.seen_len(), 1);
+    }
+
+    fn pop_all(frontier: &mut Frontier) -> Vec<String> {
+        std::iter::from_fn(|| frontier.pop()).map(|entry| entry.url).collect()
+    }
+
+    #[test]
+    fn higher_scores_are_crawled_first() {
+        let mut frontier = frontier(CrawlBudget::default());
+        frontier.push(entry("https://a.com/deep", 3), Some("a.com"));
+        frontier.push(entry("https://a.com/fresh", 1), Some("a.com"));
+        frontier.push(FrontierEntry { staleness: 0.0, ..entry("https://a.com/stale", 1) }, Some("a.com"));
+        frontier.push(FrontierEntry { sitemap_priority: Some(1.0), ..entry("https://a.com/sitemap", 1) }, Some("a.com"));
+        frontier.push(FrontierEntry { priority: 5, ..entry("https://b.com/", 0) }, None);
+        frontier.push(entry("https://a.com/", 0), None);
+        assert_eq!(pop_all(&mut frontier), vec![
+            "https://b.com/", "https://a.com/", "https://a.com/sitemap", "https://a.com/fresh", "https://a.com/stale",
+            "https://a.com/deep",
+        ]);
+    }
+
+    #[test]
+    fn ties_are_crawled_in_the_order_they_were_found() {
+        let mut frontier = frontier(CrawlBudget::default());
+        for n in 0..5 { frontier.push(entry(&format!("https://a.com/{n}"), 1), Some("a.com")); }
+        assert_eq!(pop_all(&mut frontier), (0..5).map(|n| format!("https://a.com/{n}")).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn links_from_other_hosts_raise_a_hosts_urls() {
+        let mut frontier = frontier(CrawlBudget::default());
+        frontier.push(entry("https://a.com/1", 1), Some("a.com"));
+        frontier.push(entry("https://b.com/1", 1), Some("c.com"));
+        assert_eq!(pop_all(&mut frontier), vec!["https://b.com/1", "https://a.com/1"]);
+    }
+
+    #[test]
+    fn a_queued_url_found_again_pops_once_at_its_raised_score() {
+        let mut frontier = frontier(CrawlBudget::default());
+        for n in 0..3 { frontier.push(entry(&format!("https://a.com/{n}"), 1), Some("a.com")); }
+        let before = frontier.score(&FrontierEntry { inbound: 1, ..entry("https://a.com/2", 1) });
+        assert!(!frontier.push(entry("https://a.com/2", 1), Some("a.com")));
+        assert_eq!(frontier.len(), 3);
+
+        let first = frontier.pop().unwrap();
+        assert_eq!((first.url.as_str(), first.inbound), ("https://a.com/2", 2));
+        assert!(frontier.score(&first) > before);
+        // Its old place in the heap is skipped
+        assert_eq!(pop_all(&mut frontier), vec!["https://a.com/0", "https://a.com/1"]);
+    }
+
+    #[test]
+    fn checkpoints_save_each_waiting_url_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.jsonl");
+        let mut frontier = frontier(CrawlBudget::default());
+        frontier.push(FrontierEntry { max_hops: Some(2), priority: 3, ..entry("https://a.com/", 0) }, None);
+        frontier.push(FrontierEntry { sitemap_priority: Some(0.8), staleness: 0.5, ..entry("https://a.com/1", 1) }, Some("a.com"));
+        frontier.push(entry("https://a.com/1", 1), Some("b.com"));
+        frontier.defer(entry("https://c.com/", 0), Instant::now() + std::time::Duration::from_secs(60));
+
+        assert_eq!(frontier.checkpoint(&path).unwrap(), 3);
+        assert_eq!((frontier.len(), frontier.deferred_len()), (0, 0));
+        let entries = Frontier::read_checkpoint(&path).unwrap();
+        let saved: Vec<_> = entries.iter()
+            .map(|e| (e.url.as_str(), e.depth, e.max_hops, e.priority, e.sitemap_priority, e.staleness, e.inbound))
+            .collect();
+        assert_eq!(saved, vec![
+            ("https://a.com/", 0, Some(2), 3, None, 1.0, 0),
+            ("https://a.com/1", 1, None, 0, Some(0.8), 0.5, 2),
+            ("https://c.com/", 0, None, 0, None, 1.0, 0),
+        ]);
+
+        // Resuming queues them all again, including the deferred one
+        let mut resumed = self::frontier(CrawlBudget::default());
+        for entry in entries { assert!(resumed.push(entry, None)); }
+        assert_eq!(pop_all(&mut resumed), vec!["https://a.com/", "https://c.com/", "https://a.com/1"]);
+    }
+
+    #[test]
+    fn bad_checkpoints_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.jsonl");
+        assert!(matches!(Frontier::read_checkpoint(&path), Err(CheckpointError::Io { .. })));
+        let line = serde_json::to_string(&entry("https://a.com/", 0)).unwrap();
+        fs::write(&path, format!("{line}\n\n{{\"url\": 1}}\n")).unwrap();
+        assert!(matches!(Frontier::read_checkpoint(&path), Err(CheckpointError::Parse { line: 3, .. })));
+    }
+}
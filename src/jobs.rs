@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{self, Utc};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use log::{info, warn};
+
+use crate::config::CrawlerConfig;
+use crate::crawler::CrawlerBuilder;
+use crate::db::{DB, Upsert};
+use crate::error::{JobError, RuleError};
+use crate::progress::{self, CrawlProgress};
+use crate::rules::UrlRules;
+use crate::seeds::Seed;
+
+/// Where a crawl job is up to
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Paused,
+    /// Asked to cancel, and finishing the page it's on
+    Cancelling,
+    Cancelled,
+    Finished,
+}
+
+impl JobStatus {
+    /// Whether the job is still crawling, or could carry on
+    pub fn active(&self) -> bool {
+        matches!(self, JobStatus::Running | JobStatus::Paused | JobStatus::Cancelling)
+    }
+}
+
+/// JobInfo - what's known about a crawl job
+#[derive(Clone,Debug,Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub status: JobStatus,
+    /// The URLs the job started from
+    pub seeds: Vec<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub finished_at: Option<chrono::DateTime<Utc>>,
+    pub progress: CrawlProgress,
+}
+
+/// Settings for a single crawl job, overriding the crawler config
+#[derive(Clone,Debug,Default,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobOptions {
+    /// The URLs to crawl from, as well as any enqueued, in the same format as the config's
+    pub seeds: Vec<String>,
+    pub max_pages: Option<i32>,
+    pub max_hops: Option<u32>,
+    pub max_pages_per_host: Option<usize>,
+}
+
+/// A crawl job and the handles to control it
+struct Job {
+    info: JobInfo,
+    progress: watch::Receiver<CrawlProgress>,
+    pause: watch::Sender<bool>,
+    stop: watch::Sender<bool>,
+    /// Seeds enqueued while the job runs, which the crawler adds to its frontier
+    inbox: Arc<Mutex<Vec<Seed>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Job {
+    /// The job's info with its latest progress
+    fn info(&self) -> JobInfo {
+        JobInfo { progress: self.progress.borrow().clone(), ..self.info.clone() }
+    }
+}
+
+#[derive(Default)]
+struct JobsState {
+    next_id: u64,
+    jobs: BTreeMap<u64, Job>,
+    /// Seeds waiting for the next job to start
+    pending: Vec<Seed>,
+}
+
+/// CrawlJobs - crawl jobs run in the background of the server, one at a time
+///
+/// Jobs are only kept in memory, so are forgotten when the server restarts.
+#[derive(Clone)]
+pub struct CrawlJobs {
+    db: DB,
+    config: CrawlerConfig,
+    rules: UrlRules,
+    state: Arc<Mutex<JobsState>>,
+}
+
+impl CrawlJobs {
+    /// Create a job runner, crawling with the settings from a crawler config, failing if its
+    /// rules are invalid
+    pub fn new(db: DB, config: CrawlerConfig) -> Result<CrawlJobs, RuleError> {
+        let rules = UrlRules::parse(&config.rules)?;
+        Ok(CrawlJobs { db, config, rules, state: Arc::new(Mutex::new(JobsState { next_id: 1, ..Default::default() })) })
+    }
+
+    /// The allow and deny rules jobs crawl with
+    pub fn rules(&self) -> &UrlRules {
+        &self.rules
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, JobsState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Add seeds to the running job's frontier, or for the next job to crawl from if none is
+    /// running, returning how many are waiting
+    pub fn enqueue(&self, seeds: &[String]) -> Result<usize, JobError> {
+        let seeds = Seed::parse_all(seeds, "request")?;
+        let mut state = self.lock();
+        if let Some(job) = state.jobs.values().find(|job| job.info.status.active()) {
+            let mut inbox = job.inbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            inbox.extend(seeds);
+            return Ok(inbox.len());
+        }
+        state.pending.extend(seeds);
+        Ok(state.pending.len())
+    }
+
+    /// Start a job from the seeds given and any enqueued, if no other job is running
+    pub fn start(&self, options: JobOptions) -> Result<JobInfo, JobError> {
+        let mut seeds = Seed::parse_all(&options.seeds, "request")?;
+
+        let mut config = self.config.clone();
+        if let Some(max_pages) = options.max_pages { config.max_pages = max_pages }
+        if options.max_hops.is_some() { config.max_hops = options.max_hops }
+        if options.max_pages_per_host.is_some() { config.max_pages_per_host = options.max_pages_per_host }
+        let builder = CrawlerBuilder::from_config(&config)?;
+
+        let mut state = self.lock();
+        if let Some(job) = state.jobs.values().find(|job| job.info.status.active()) {
+            return Err(JobError::AlreadyRunning { id: job.info.id });
+        }
+        seeds.append(&mut state.pending);
+        if seeds.is_empty() { return Err(JobError::NoSeeds) }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        let (progress_sender, progress) = progress::channel();
+        let (pause, pause_receiver) = watch::channel(false);
+        let (stop, stop_receiver) = watch::channel(false);
+        let inbox = Arc::new(Mutex::new(Vec::new()));
+        let mut crawler = builder.progress(progress_sender)
+                                 .pause(pause_receiver)
+                                 .shutdown(stop_receiver)
+                                 .inbox(inbox.clone())
+                                 .build();
+
+        let info = JobInfo {
+            id,
+            status: JobStatus::Running,
+            seeds: seeds.iter().map(|seed| seed.url.clone()).collect(),
+            created_at: Utc::now(),
+            finished_at: None,
+            progress: CrawlProgress::default(),
+        };
+        info!("Starting crawl job {id} from {} seeds", seeds.len());
+
+        let jobs = self.clone();
+        let task = tokio::spawn(async move {
+            crawler.crawl(&jobs.db, &seeds).await;
+            let mut state = jobs.lock();
+            let mut left = Vec::new();
+            if let Some(job) = state.jobs.get_mut(&id) {
+                job.info.status = if *job.stop.borrow() { JobStatus::Cancelled } else { JobStatus::Finished };
+                job.info.finished_at = Some(Utc::now());
+                info!("Crawl job {id} {:?}", job.info.status);
+                // Seeds enqueued after the crawl stopped taking them are kept for the next job
+                left = std::mem::take(&mut *job.inbox.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+            }
+            state.pending.append(&mut left);
+        });
+
+        let job = Job { info, progress, pause, stop, inbox, task: Some(task) };
+        let info = job.info();
+        state.jobs.insert(id, job);
+        Ok(info)
+    }
+
+    /// Every job, oldest first
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.lock().jobs.values().map(Job::info).collect()
+    }
+
+    /// A single job
+    pub fn get(&self, id: u64) -> Result<JobInfo, JobError> {
+        self.lock().jobs.get(&id).map(Job::info).ok_or(JobError::NotFound { id })
+    }
+
+    /// Pause a running job, after the page it's on
+    pub fn pause(&self, id: u64) -> Result<JobInfo, JobError> {
+        self.control(id, JobStatus::Running, JobStatus::Paused, |job| { job.pause.send_replace(true); })
+    }
+
+    /// Carry on with a paused job
+    pub fn resume(&self, id: u64) -> Result<JobInfo, JobError> {
+        self.control(id, JobStatus::Paused, JobStatus::Running, |job| { job.pause.send_replace(false); })
+    }
+
+    /// Stop a running or paused job, after the page it's on
+    pub fn cancel(&self, id: u64) -> Result<JobInfo, JobError> {
+        let mut state = self.lock();
+        let job = state.jobs.get_mut(&id).ok_or(JobError::NotFound { id })?;
+        if !matches!(job.info.status, JobStatus::Running | JobStatus::Paused) {
+            return Err(JobError::WrongStatus { id, status: job.info.status });
+        }
+        job.stop.send_replace(true);
+        job.info.status = JobStatus::Cancelling;
+        Ok(job.info())
+    }
+
+    /// Move a job from one status to another, if it's in the first
+    fn control(&self, id: u64, from: JobStatus, to: JobStatus, action: impl FnOnce(&Job)) -> Result<JobInfo, JobError> {
+        let mut state = self.lock();
+        let job = state.jobs.get_mut(&id).ok_or(JobError::NotFound { id })?;
+        if job.info.status != from {
+            return Err(JobError::WrongStatus { id, status: job.info.status });
+        }
+        action(job);
+        job.info.status = to;
+        Ok(job.info())
+    }
+
+    /// The progress of the job running now, or failing that the latest one
+    pub fn progress(&self) -> Option<watch::Receiver<CrawlProgress>> {
+        let state = self.lock();
+        state.jobs.values().find(|job| job.info.status.active())
+            .or_else(|| state.jobs.values().next_back())
+            .map(|job| job.progress.clone())
+    }
+
+    /// Whether a job is running now
+    pub fn active(&self) -> bool {
+        self.lock().jobs.values().any(|job| job.info.status.active())
+    }
+
+    /// Fetch and index a URL now, however recently it was indexed
+    pub async fn reindex(&self, url: &str) -> Result<Upsert, JobError> {
+        let mut crawler = CrawlerBuilder::from_config(&self.config)?.build();
+        Ok(crawler.reindex(&self.db, url).await?)
+    }
+
+    /// Stop any running job, and wait for it to finish the page it's on
+    pub async fn shutdown(&self) {
+        let tasks: Vec<_> = {
+            let mut state = self.lock();
+            state.jobs.values_mut().filter_map(|job| {
+                job.stop.send_replace(true);
+                job.task.take()
+            }).collect()
+        };
+        for task in tasks {
+            if let Err(e) = task.await { warn!("Crawl job failed: {e}") }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+/// A language with a PostgreSQL text search configuration to stem it with
+pub struct Language {
+    /// ISO 639-1 codes, the first being the one stored
+    codes: &'static [&'static str],
+    /// The text search configuration (`regconfig`)
+    pub config: &'static str,
+    /// Common words, for spotting the language of untagged pages
+    stopwords: &'static [&'static str],
+}
+
+impl Language {
+    /// The code pages in this language are stored with
+    pub fn code(&self) -> &'static str {
+        self.codes[0]
+    }
+}
+
+/// The configuration for pages in languages without their own, which doesn't stem words
+pub const FALLBACK_CONFIG: &str = "simple";
+
+/// The fewest stopwords a page needs for its language to be guessed from them
+const MIN_STOPWORDS: usize = 3;
+
+/// How many words of a page are looked at to guess its language
+const SAMPLE_WORDS: usize = 1000;
+
+/// Every language PostgreSQL has a text search configuration for
+const LANGUAGES: &[Language] = &[
+    Language { codes: &["ar"], config: "arabic", stopwords: &[] },
+    Language { codes: &["hy"], config: "armenian", stopwords: &[] },
+    Language { codes: &["eu"], config: "basque", stopwords: &[] },
+    Language { codes: &["ca"], config: "catalan", stopwords: &[] },
+    Language { codes: &["da"], config: "danish", stopwords: &["og", "det", "er", "af", "til", "en", "ikke", "med", "jeg", "som", "har", "på"] },
+    Language { codes: &["nl"], config: "dutch", stopwords: &["de", "het", "een", "en", "van", "is", "dat", "niet", "zijn", "op", "voor", "met"] },
+    Language { codes: &["en"], config: "english", stopwords: &["the", "and", "of", "to", "is", "in", "that", "it", "for", "with", "was", "this", "are", "you"] },
+    Language { codes: &["fi"], config: "finnish", stopwords: &["ja", "on", "ei", "että", "se", "oli", "hän", "mutta", "kun", "ovat", "tai", "myös"] },
+    Language { codes: &["fr"], config: "french", stopwords: &["le", "la", "les", "et", "des", "est", "une", "du", "que", "pour", "dans", "qui", "pas", "sur"] },
+    Language { codes: &["de"], config: "german", stopwords: &["der", "die", "und", "das", "ist", "nicht", "ein", "eine", "mit", "von", "den", "zu", "auf", "sich"] },
+    Language { codes: &["el"], config: "greek", stopwords: &["και", "το", "να", "του", "η", "της", "με", "που", "την", "από", "είναι", "για"] },
+    Language { codes: &["hi"], config: "hindi", stopwords: &[] },
+    Language { codes: &["hu"], config: "hungarian", stopwords: &["a", "az", "és", "hogy", "nem", "egy", "is", "van", "meg", "de", "ez", "csak"] },
+    Language { codes: &["id"], config: "indonesian", stopwords: &["yang", "dan", "di", "itu", "dengan", "untuk", "tidak", "ini", "dari", "dalam", "akan", "pada"] },
+    Language { codes: &["ga"], config: "irish", stopwords: &[] },
+    Language { codes: &["it"], config: "italian", stopwords: &["il", "di", "che", "e", "la", "per", "un", "non", "sono", "della", "una", "del", "gli"] },
+    Language { codes: &["lt"], config: "lithuanian", stopwords: &[] },
+    Language { codes: &["ne"], config: "nepali", stopwords: &[] },
+    Language { codes: &["no", "nb", "nn"], config: "norwegian", stopwords: &["og", "det", "er", "ikke", "som", "til", "jeg", "på", "med", "har", "av", "ble"] },
+    Language { codes: &["pt"], config: "portuguese", stopwords: &["o", "de", "que", "e", "do", "da", "em", "um", "para", "não", "uma", "os", "com"] },
+    Language { codes: &["ro"], config: "romanian", stopwords: &["și", "în", "nu", "este", "la", "cu", "pe", "care", "mai", "sau", "din", "pentru"] },
+    Language { codes: &["ru"], config: "russian", stopwords: &["и", "в", "не", "на", "что", "я", "с", "он", "как", "это", "по", "но", "из"] },
+    Language { codes: &["sr"], config: "serbian", stopwords: &[] },
+    Language { codes: &["es"], config: "spanish", stopwords: &["el", "la", "de", "que", "y", "en", "los", "del", "se", "las", "por", "un", "para", "con"] },
+    Language { codes: &["sv"], config: "swedish", stopwords: &["och", "det", "att", "är", "som", "en", "på", "för", "med", "inte", "av", "till"] },
+    Language { codes: &["ta"], config: "tamil", stopwords: &[] },
+    Language { codes: &["tr"], config: "turkish", stopwords: &["ve", "bir", "bu", "da", "de", "için", "ile", "çok", "gibi", "ama", "daha", "olarak"] },
+    Language { codes: &["yi"], config: "yiddish", stopwords: &[] },
+];
+
+/// Find a language by a code like `de` or `de-AT`, or a configuration name like `german`
+pub fn find(name: &str) -> Option<&'static Language> {
+    let name = name.trim().to_lowercase();
+    let primary = primary_subtag(&name);
+    LANGUAGES.iter().find(|lang| lang.config == name || lang.codes.contains(&primary))
+}
+
+/// The text search configuration for pages stored with a language code
+pub fn config_for(code: Option<&str>) -> &'static str {
+    code.and_then(find).map_or(FALLBACK_CONFIG, |lang| lang.config)
+}
+
+/// Work out the language of a page, as a lower case ISO 639 code
+///
+/// `<html lang>` is trusted first, then the `Content-Language` header, then whichever language's
+/// common words turn up most in the text. Tagged languages are kept even if there's no
+/// configuration for them, to filter on.
+pub fn detect(html_lang: Option<&str>, content_language: Option<&str>, text: &str) -> Option<String> {
+    let tagged = html_lang.into_iter()
+        .chain(content_language.and_then(|header| header.split(',').next()))
+        .map(|tag| primary_subtag(&tag.trim().to_lowercase()).to_string())
+        .find(|code| is_code(code));
+    match tagged {
+        Some(code) => Some(find(&code).map_or(code, |lang| lang.code().to_string())),
+        None => from_stopwords(text).map(|lang| lang.code().to_string()),
+    }
+}
+
+/// Whether text looks like a language code, which are 2 or 3 letters
+pub fn is_code(code: &str) -> bool {
+    (2..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_lowercase())
+}
+
+/// The language part of a tag, like `de` from `de-AT`
+fn primary_subtag(tag: &str) -> &str {
+    tag.split(['-', '_']).next().unwrap_or_default()
+}
+
+/// The language with the most common words in a sample of the text, if there are enough
+fn from_stopwords(text: &str) -> Option<&'static Language> {
+    let words: Vec<String> = text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .take(SAMPLE_WORDS)
+        .map(str::to_lowercase)
+        .collect();
+    let threshold = MIN_STOPWORDS.max(words.len() / 20);
+    LANGUAGES.iter()
+        .filter(|lang| !lang.stopwords.is_empty())
+        .map(|lang| (lang, words.iter().filter(|word| lang.stopwords.contains(&word.as_str())).count()))
+        .filter(|(_, hits)| *hits >= threshold)
+        .max_by_key(|(_, hits)| *hits)
+        .map(|(lang, _)| lang)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_found_by_code_or_config() {
+        assert_eq!(find("de").map(|lang| lang.config), Some("german"));
+        assert_eq!(find(" DE-at ").map(|lang| lang.config), Some("german"));
+        assert_eq!(find("pt_BR").map(|lang| lang.config), Some("portuguese"));
+        assert_eq!(find("German").map(|lang| lang.code()), Some("de"));
+        assert_eq!(find("nn").map(|lang| lang.code()), Some("no"));
+        assert!(find("ja").is_none());
+        assert!(find("").is_none());
+        assert_eq!(config_for(Some("fr")), "french");
+        assert_eq!(config_for(Some("ja")), FALLBACK_CONFIG);
+        assert_eq!(config_for(None), FALLBACK_CONFIG);
+    }
+
+    #[test]
+    fn codes_are_two_or_three_lower_case_letters() {
+        assert!(is_code("de"));
+        assert!(is_code("gsw"));
+        assert!(!is_code("d"));
+        assert!(!is_code("deut"));
+        assert!(!is_code("DE"));
+        assert!(!is_code("d3"));
+        assert!(!is_code("dé"));
+    }
+
+    #[test]
+    fn tags_are_trusted_before_the_text() {
+        let english = "the cat and the dog is in the house";
+        assert_eq!(detect(Some("de-DE"), Some("fr"), english).as_deref(), Some("de"));
+        assert_eq!(detect(None, Some("fr-CA, en;q=0.8"), english).as_deref(), Some("fr"));
+        assert_eq!(detect(Some("nb"), None, english).as_deref(), Some("no"));
+        // Tags without a configuration are kept, and invalid ones are passed over
+        assert_eq!(detect(Some("ja"), None, english).as_deref(), Some("ja"));
+        assert_eq!(detect(Some("x-klingon"), Some("de"), english).as_deref(), Some("de"));
+        assert_eq!(detect(Some(""), None, english).as_deref(), Some("en"));
+    }
+
+    #[test]
+    fn untagged_text_is_detected_from_common_words() {
+        let german = "Diese Seite ist auf Deutsch und handelt von der Suche, die nicht mit dem Crawler endet";
+        assert_eq!(detect(None, None, german).as_deref(), Some("de"));
+        let french = "Le chat est dans la maison, et les enfants sont sur le lit pour la nuit";
+        assert_eq!(detect(None, None, french).as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn short_text_is_not_guessed() {
+        assert_eq!(detect(None, None, ""), None);
+        assert_eq!(detect(None, None, "the cat"), None);
+        assert_eq!(detect(None, None, "rust postgres tokio axum"), None);
+    }
+
+    #[test]
+    fn mixed_text_is_the_language_with_the_most_common_words() {
+        let mixed = "the cat and the dog is in the house with the mouse. Der Hund und die Katze";
+        assert_eq!(detect(None, None, mixed).as_deref(), Some("en"));
+        // A few words in another language among many without common words aren't enough
+        let mostly_names = format!("{} the cat and the dog", "tokio axum sqlx serde ".repeat(30));
+        assert_eq!(detect(None, None, &mostly_names), None);
+    }
+}
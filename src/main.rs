@@ -1,74 +1,461 @@
-use log::{debug, info};
-use std::env;
-use clap::Parser;
+use log::{debug, info, warn};
+use std::path::PathBuf;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::fs::File;
+use clap::{Args, Parser, Subcommand};
+use futures_util::TryStreamExt;
 
 mod crawler;
 mod db;
 mod app;
+mod config;
+mod error;
+mod backoff;
+mod frontier;
+mod jobs;
+mod lang;
+mod rules;
+mod progress;
+mod query;
+mod seeds;
+mod seen;
+mod shutdown;
+mod submissions;
+mod traps;
 
 use crate::crawler::CrawlerBuilder;
 use crate::app::serve;
-use crate::db::DB;
+use crate::config::{Config, CrawlerConfig, ServerConfig};
+use crate::db::{DB, ExportedPage, SearchMode, Upsert};
+use crate::frontier::Frontier;
+use crate::jobs::CrawlJobs;
+use crate::rules::{RuleAction, UrlRules, Verdict};
+use crate::seeds::Seed;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = "A simple search engine in rust")]
-struct Args {
-    #[arg(index = 1, help = "either 'serve' or 'crawl'")]
-    command: String,
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 
-    #[arg(long,short,default_value_t=String::new(),help="the url to crawl")]
-    url: String,
+    #[arg(long,short,global=true,env="HUNTER_CONFIG",help="Optional - the config file to use (defaults to ./hunter-searcher.toml if it exists)")]
+    config: Option<PathBuf>,
 
-    #[arg(long,short,default_value_t=1,help="the max amount of pages to crawl (set to -1 to infinitely crawl)")]
-    depth: i32,
+    #[arg(long,global=true,help="print the effective config, after applying the environment and flags, and exit")]
+    print_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Crawl the web from seed URLs, adding the pages found to the index
+    Crawl(CrawlArgs),
+    /// Run the search engine web server
+    Serve(ServeArgs),
+    /// Search the index from the command line
+    Search(SearchArgs),
+    /// Show statistics about the index
+    Stats,
+    /// Summarise a crawl from the fetch log: outcomes, error rates and slow hosts
+    Report(ReportArgs),
+    /// Export the index as JSON lines
+    Export(ExportArgs),
+    /// Import pages into the index from a JSON lines export
+    Import(ImportArgs),
+    /// Create or update the database schema
+    Migrate,
+}
+
+#[derive(Args, Debug)]
+struct CrawlArgs {
+    #[arg(help="the URLs to start crawling from, each optionally followed by ' hops=<n> priority=<n>' (defaults to the seeds in the config file)")]
+    seeds: Vec<String>,
+
+    #[arg(long,short,help="Optional - another URL to crawl from (repeatable)")]
+    url: Vec<String>,
+
+    #[arg(long,short='f',help="Optional - a file of seeds to crawl from, one per line (repeatable)")]
+    seed_file: Vec<PathBuf>,
+
+    #[arg(long,short='d',visible_alias="depth",help="the max amount of pages to crawl (set to -1 to infinitely crawl)")]
+    max_pages: Option<i32>,
+
+    #[arg(long,help="Optional - the max amount of links to follow from the seed")]
+    max_hops: Option<u32>,
+
+    #[arg(long,help="Optional - the max amount of pages to crawl from any one host")]
+    max_pages_per_host: Option<usize>,
+
+    #[arg(long,help="Optional - the max amount of bytes to download")]
+    max_bytes: Option<u64>,
+
+    #[arg(long="rule",short,help="Optional - an allow or deny rule, like 'deny:*.pdf' or 'allow:re:^https://example\\.com/' (repeatable, the first to match wins, replaces the config file's rules)")]
+    rules: Vec<String>,
+
+    #[arg(long,help="Optional - show which rule applies to a URL, without crawling")]
+    explain_url: Option<String>,
 
-    #[arg(long,short,default_value_t=String::new(),help="Optional - the patterns to whitelist the crawler to (comma-seperated)")]
-    whitelist: String,
+    #[arg(long,help="Optional - serve live progress on this address, like '127.0.0.1:22002'")]
+    progress: Option<String>,
 
-    #[arg(long,short,default_value_t=String::new(),help="Optional - the patterns to blacklist the crawler to (comma-seperated)")]
-    blacklist: String,
+    #[arg(long,help="Optional - carry on from the URLs left by a crawl that was stopped early")]
+    resume: bool,
+}
+
+impl CrawlArgs {
+    /// Override the crawler config with any flags given
+    fn apply(&self, config: &mut CrawlerConfig) {
+        if !self.seeds.is_empty() || !self.url.is_empty() || !self.seed_file.is_empty() {
+            config.seeds = self.seeds.iter().chain(&self.url).cloned().collect();
+            config.seed_files = self.seed_file.clone();
+        }
+        if let Some(max_pages) = self.max_pages { config.max_pages = max_pages }
+        if self.max_hops.is_some() { config.max_hops = self.max_hops }
+        if self.max_pages_per_host.is_some() { config.max_pages_per_host = self.max_pages_per_host }
+        if self.max_bytes.is_some() { config.max_bytes = self.max_bytes }
+        if !self.rules.is_empty() { config.rules = self.rules.clone() }
+        if self.progress.is_some() { config.progress_bind = self.progress.clone() }
+    }
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    #[arg(long,help="the address for the server to bind to (HUNTER_BIND)")]
+    bind: Option<String>,
+
+    #[arg(long,short,help="the port for the server to listen on (HUNTER_PORT)")]
+    port: Option<u16>,
+
+    #[arg(long,help="Optional - the path prefix to serve under, e.g. '/search-engine/' behind a reverse proxy (HUNTER_BASE_PATH)")]
+    base_path: Option<String>,
+
+    #[arg(long,help="Optional - listen on this unix domain socket instead of a TCP port (HUNTER_UNIX_SOCKET)")]
+    unix_socket: Option<PathBuf>,
+
+    #[arg(long,help="Optional - the URL the server is reached at publicly, e.g. 'https://example.com/search-engine' (HUNTER_PUBLIC_URL)")]
+    public_url: Option<String>,
+
+    #[arg(long,help="Optional - the progress endpoint of a running crawl to show on the dashboard, like 'http://127.0.0.1:22002'")]
+    crawl_progress: Option<String>,
+}
+
+impl ServeArgs {
+    /// Override the server config with any flags given
+    fn apply(&self, config: &mut ServerConfig) {
+        if let Some(bind) = &self.bind { config.bind = bind.clone() }
+        if let Some(port) = self.port { config.port = port }
+        if let Some(base_path) = &self.base_path { config.base_path = base_path.clone() }
+        if let Some(unix_socket) = &self.unix_socket { config.unix_socket = Some(unix_socket.clone()) }
+        if let Some(url) = &self.public_url { config.public_url = Some(url.clone()) }
+        if let Some(url) = &self.crawl_progress { config.crawl_progress_url = Some(url.clone()) }
+    }
+}
+
+#[derive(Args, Debug)]
+struct SearchArgs {
+    #[arg(required=true,help="the search terms")]
+    query: Vec<String>,
+
+    #[arg(long,short='n',default_value_t=10,help="the max amount of results to show")]
+    limit: usize,
+
+    #[arg(long,short,default_value="standard",help="how to match the terms: standard, fuzzy (by prefix, and similar titles and URLs) or auto (fuzzy if standard finds nothing)")]
+    mode: SearchMode,
+
+    #[arg(long,help="show the ranking weights, and what each result's score is made up of")]
+    debug: bool,
+}
+
+#[derive(Args, Debug)]
+struct ReportArgs {
+    #[arg(long,help="Optional - the id of the crawl to report on (defaults to the latest)")]
+    crawl: Option<i64>,
+
+    #[arg(long,short='n',default_value_t=10,help="the max amount of rows to show in each section")]
+    limit: i64,
+}
+
+#[derive(Args, Debug)]
+struct ExportArgs {
+    #[arg(long,short,help="Optional - the file to write to (defaults to stdout)")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ImportArgs {
+    #[arg(long,short,help="Optional - the file to read from (defaults to stdin)")]
+    input: Option<PathBuf>,
+
+    #[arg(long,short,help="replace pages even if they are already in the index unchanged")]
+    force: bool,
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let args = Args::parse();
-    
-    let db = DB::new(
-        &env::var("POSTGRES_USER").expect("No POSTGRES_USER env var set!"), 
-        &env::var("POSTGRES_PASSWORD").expect("No POSTGRES_PASSWORD env var set!"), 
-        &env::var("POSTGRES_HOST").expect("No POSTGRES_HOST env var set!"), 
-        &env::var("POSTGRES_DB").expect("No POSTGRES_DB env var set!")
-        ).await;
-
-    if args.command == "crawl" {
-        if args.url == "" { panic!("Cannot crawl a blank URL!"); }
-
-        let mut url = args.url;
-        if !url.starts_with("http") { url = "http://".to_owned() + &url }
-        info!("Started crawler!");
-        
-        let mut crawler_builder = CrawlerBuilder::new("hunter-searcher crawler/v0.1.0")
-                                        .max_depth(args.depth);
-        if args.whitelist != String::new() {
-            crawler_builder = crawler_builder.add_whitelist(
-                args.whitelist.split(",").map(|s| s.to_string()).collect()
-            );
+    let cli = Cli::parse();
+
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => { eprintln!("{e}"); std::process::exit(1) }
+    };
+    match &cli.command {
+        Command::Crawl(args) => args.apply(&mut config.crawler),
+        Command::Serve(args) => args.apply(&mut config.server),
+        _ => {}
+    }
+
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+    if let Command::Crawl(CrawlArgs { explain_url: Some(url), .. }) = &cli.command {
+        explain_url(&config.crawler, url);
+        return;
+    }
+
+    let db = match DB::new(&config.database).await {
+        Ok(db) => db.with_ranking(config.ranking.clone()),
+        Err(e) => { eprintln!("Failed to connect to postgres server: {e}"); std::process::exit(1) }
+    };
+
+    match cli.command {
+        Command::Crawl(args) => crawl(&db, &config.crawler, args.resume).await,
+        Command::Serve(_) => {
+            let jobs = match CrawlJobs::new(db.clone(), config.crawler) {
+                Ok(jobs) => jobs,
+                Err(e) => { eprintln!("{e}"); std::process::exit(1) }
+            };
+            serve(db, config.server, jobs, shutdown::listen()).await
+        },
+        Command::Search(args) => search(&db, args).await,
+        Command::Stats => stats(&db).await,
+        Command::Report(args) => report(&db, args).await,
+        Command::Export(args) => export(&db, args).await,
+        Command::Import(args) => import(&db, args).await,
+        Command::Migrate => migrate(&db).await,
+    }
+}
+
+/// Run the crawler from the crawler config
+async fn crawl(db: &DB, config: &CrawlerConfig, resume: bool) {
+    let seeds = match Seed::from_config(config) {
+        Ok(seeds) => seeds,
+        Err(e) => { eprintln!("{e}"); std::process::exit(1) }
+    };
+    let resumed = match (&config.checkpoint_file, resume) {
+        (Some(path), true) => match Frontier::read_checkpoint(path) {
+            Ok(entries) => { info!("Resuming {} URLs from {}", entries.len(), path.display()); entries },
+            Err(e) => { eprintln!("{e}"); std::process::exit(1) }
+        },
+        (None, true) => { eprintln!("Can't resume without a checkpoint_file set"); std::process::exit(1) },
+        _ => Vec::new(),
+    };
+    if seeds.is_empty() && resumed.is_empty() {
+        eprintln!("No URL to crawl! Pass some seeds or set them in the config file");
+        std::process::exit(1)
+    }
+
+    info!("Started crawler!");
+
+    let mut crawler_builder = match CrawlerBuilder::from_config(config) {
+        Ok(builder) => builder,
+        Err(e) => { eprintln!("{e}"); std::process::exit(1) }
+    };
+    if let Some(path) = &config.checkpoint_file {
+        crawler_builder = crawler_builder.checkpoint(path.clone());
+    }
+    let shutdown = shutdown::listen();
+    crawler_builder = crawler_builder.resume(resumed).shutdown(shutdown.clone());
+    if let Some(addr) = &config.progress_bind {
+        let (sender, receiver) = progress::channel();
+        tokio::spawn(progress::serve(addr.clone(), receiver, shutdown));
+        crawler_builder = crawler_builder.progress(sender);
+    }
+    let mut crawler = crawler_builder.build();
+
+    debug!("Created Crawler from builder");
+
+    let _ = crawler.crawl(db, &seeds).await;
+    db.close().await;
+}
+
+/// Compile the crawler's allow and deny rules, exiting if any are invalid
+fn parse_rules(config: &CrawlerConfig) -> UrlRules {
+    match UrlRules::parse(&config.rules) {
+        Ok(rules) => rules,
+        Err(e) => { eprintln!("{e}"); std::process::exit(1) }
+    }
+}
+
+/// Print which of the crawler's rules applies to a URL
+fn explain_url(config: &CrawlerConfig, url: &str) {
+    let rules = parse_rules(config);
+    match rules.check(url) {
+        Verdict::Rule { index, rule } => {
+            println!("{url}: {} by rule {} ({rule})", if rule.action == RuleAction::Allow { "allowed" } else { "denied" }, index + 1);
+        },
+        Verdict::Default(_) if config.rules.is_empty() => {
+            println!("{url}: allowed, as there are no rules");
+        },
+        Verdict::Default(RuleAction::Deny) => {
+            println!("{url}: denied, as no rule matched and there are allow rules");
+        },
+        Verdict::Default(RuleAction::Allow) => {
+            println!("{url}: allowed, as no rule matched and there are only deny rules");
+        },
+    }
+}
+
+/// Print search results for a query to stdout
+async fn search(db: &DB, args: SearchArgs) {
+    let query = args.query.join(" ");
+    let response = match db.search_with_suggestion(&query, args.mode).await {
+        Ok(response) => response,
+        Err(e) => { eprintln!("Search failed: {e}"); std::process::exit(1) }
+    };
+
+    if args.debug {
+        let r = db.ranking();
+        println!("Weights: text {}, freshness {} (half life {} days), js -{}, depth -{}, authority {}, min score {}\n",
+                 r.text_weight, r.freshness_weight, r.freshness_half_life_days, r.js_penalty, r.url_depth_penalty,
+                 r.authority_weight, r.min_score);
+    }
+    if let Some(suggestion) = &response.suggestion { println!("Did you mean \"{suggestion}\"?\n") }
+    if response.results.is_empty() { println!("No results for \"{query}\""); return }
+
+    for res in response.results.iter().take(args.limit) {
+        println!("{}\n  {}\n  {}", res.title, res.url, res.blurb.as_deref().unwrap_or_default());
+        if let Some(score) = res.score.as_ref().filter(|_| args.debug) {
+            println!("  score {:.4} = text {:.4} + freshness {:.4} + js {:.4} + depth {:.4} + authority {:.4}",
+                     score.total, score.text, score.freshness, score.js, score.depth, score.authority);
         }
-        if args.blacklist != String::new() {
-            crawler_builder = crawler_builder.add_blacklist(
-                args.blacklist.split(",").map(|s| s.to_string()).collect()
-            );
+        println!();
+    }
+}
+
+/// Print statistics about the index to stdout
+async fn stats(db: &DB) {
+    let stats = match db.stats().await {
+        Ok(stats) => stats,
+        Err(e) => { eprintln!("Failed to get index stats: {e}"); std::process::exit(1) }
+    };
+
+    println!("Pages:       {}", stats.pages);
+    println!("Hosts:       {}", stats.hosts);
+    println!("JS scripts:  {}", stats.number_js);
+    if let (Some(oldest), Some(newest)) = (stats.oldest, stats.newest) {
+        println!("Oldest page: {oldest}");
+        println!("Newest page: {newest}");
+    }
+}
+
+/// Print a summary of a crawl from the fetch log to stdout
+async fn report(db: &DB, args: ReportArgs) {
+    let report = match db.crawl_report(args.crawl, args.limit).await {
+        Ok(Some(report)) => report,
+        Ok(None) => { println!("No crawls found"); return }
+        Err(e) => { eprintln!("Failed to build crawl report: {e}"); std::process::exit(1) }
+    };
+
+    let crawl = &report.crawl;
+    println!("Crawl {} started {}", crawl.id, crawl.started_at);
+    match crawl.finished_at {
+        Some(finished_at) => println!("  finished {finished_at} (took {}s)", (finished_at - crawl.started_at).num_seconds()),
+        None => println!("  not finished"),
+    }
+
+    let total: i64 = report.outcomes.iter().map(|o| o.count).sum();
+    println!("\nOutcomes ({total} URLs)");
+    for o in &report.outcomes {
+        println!("  {:<15} {:>7} {:>6.1}%", o.outcome, o.count, 100.0 * o.count as f64 / total as f64);
+    }
+
+    println!("\nHosts by error rate");
+    if report.error_hosts.is_empty() { println!("  (no errors)") }
+    for h in &report.error_hosts {
+        println!("  {:<40} {:>5}/{:<5} {:>6.1}%", h.host, h.errors, h.fetches, 100.0 * h.error_rate);
+    }
+
+    println!("\nSlowest hosts");
+    for h in &report.slow_hosts {
+        println!("  {:<40} avg {:>7.0}ms  max {:>6}ms  ({} fetches)", h.host, h.avg_ms, h.max_ms, h.fetches);
+    }
+
+    println!("\nTop failing paths");
+    if report.failing_paths.is_empty() { println!("  (no failures)") }
+    for p in &report.failing_paths {
+        println!("  {:<50} {:>5} failures on {} hosts", p.path, p.failures, p.hosts);
+    }
+}
+
+/// Write every page in the index as a line of JSON
+async fn export(db: &DB, args: ExportArgs) {
+    let mut out: Box<dyn Write> = match args.output {
+        Some(path) => match File::create(&path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => { eprintln!("Failed to create {}: {e}", path.display()); std::process::exit(1) }
+        },
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let mut pages = db.export_pages();
+    let mut count = 0;
+    loop {
+        let page = match pages.try_next().await {
+            Ok(Some(page)) => page,
+            Ok(None) => break,
+            Err(e) => { eprintln!("Failed to read page from database: {e}"); std::process::exit(1) }
+        };
+        if let Err(e) = serde_json::to_writer(&mut out, &page).map_err(io::Error::from).and_then(|()| writeln!(out)) {
+            eprintln!("Failed to write page: {e}");
+            std::process::exit(1)
         }
-        let mut crawler = crawler_builder.build();
-        
-        debug!("Created Crawler from builder");
+        count += 1;
+    }
+    if let Err(e) = out.flush() { eprintln!("Failed to write pages: {e}"); std::process::exit(1) }
+    info!("Exported {count} pages");
+}
 
-        let _ = crawler.crawl(&db, &url).await;
+/// Add every page in a JSON lines export to the index
+async fn import(db: &DB, args: ImportArgs) {
+    let input: Box<dyn BufRead> = match args.input {
+        Some(path) => match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => { eprintln!("Failed to open {}: {e}", path.display()); std::process::exit(1) }
+        },
+        None => Box::new(BufReader::new(io::stdin().lock())),
+    };
+
+    let (mut inserted, mut updated, mut unchanged, mut failed) = (0, 0, 0, 0);
+    for (n, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => { eprintln!("Failed to read line {}: {e}", n + 1); std::process::exit(1) }
+        };
+        if line.trim().is_empty() { continue }
+
+        let page: ExportedPage = match serde_json::from_str(&line) {
+            Ok(page) => page,
+            Err(e) => { warn!("Skipping line {}: {e}", n + 1); continue }
+        };
+        match db.import_page(page, args.force).await {
+            Ok(Upsert::Inserted(_)) => inserted += 1,
+            Ok(Upsert::Updated(_)) => updated += 1,
+            Ok(Upsert::Unchanged(_)) => unchanged += 1,
+            Err(e) => { warn!("Skipping line {}: {e}", n + 1); failed += 1 }
+        }
     }
+    println!("Imported pages: {inserted} added, {updated} updated, {unchanged} unchanged, {failed} failed");
+    if inserted + updated > 0 {
+        if let Err(e) = db.refresh_terms().await { warn!("Couldn't refresh the suggestion and spelling dictionaries: {e}") }
+    }
+}
 
-    if args.command == "serve" {
-        serve(db).await;
+/// Bring the database schema up to date
+async fn migrate(db: &DB) {
+    match db.migrate().await {
+        Ok(()) => info!("Database schema is up to date"),
+        Err(e) => { eprintln!("{e}"); std::process::exit(1) }
     }
 }
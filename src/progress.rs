@@ -0,0 +1,92 @@
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Json, Router,
+};
+use futures_util::stream::{self, Stream};
+use serde::Serialize;
+use sqlx::types::chrono::{self, Utc};
+use tokio::sync::watch;
+use log::{info, error};
+
+/// CrawlProgress - a snapshot of how a running crawl is going
+#[derive(Clone,Debug,Default,Serialize)]
+pub struct CrawlProgress {
+    /// The id of the crawl in the fetch log
+    pub crawl_id: Option<i64>,
+    /// Whether the crawl is still going
+    pub running: bool,
+    /// Whether the crawl is paused
+    pub paused: bool,
+    pub started_at: Option<chrono::DateTime<Utc>>,
+    pub elapsed_secs: f64,
+    /// The pages crawled and indexed
+    pub pages: usize,
+    /// The bytes downloaded for those pages
+    pub bytes: u64,
+    /// The URLs waiting to be crawled, and waiting on paused hosts
+    pub queued: usize,
+    pub deferred: usize,
+    /// The URLs found so far, crawled or not
+    pub seen: usize,
+    /// The hosts crawled so far
+    pub hosts: usize,
+    /// The URLs that couldn't be fetched or indexed
+    pub errors: usize,
+    /// The URLs quarantined as crawl traps
+    pub quarantined: usize,
+    pub pages_per_sec: f64,
+    pub bytes_per_sec: f64,
+    /// The URL being crawled right now
+    pub current_url: Option<String>,
+}
+
+/// Start a channel for a crawl to report its progress on
+pub fn channel() -> (watch::Sender<CrawlProgress>, watch::Receiver<CrawlProgress>) {
+    watch::channel(CrawlProgress::default())
+}
+
+/// Serve a crawl's progress on `addr`, as JSON at `/progress` and as server-sent events at
+/// `/progress/events`, with the events ending once `shutdown` turns true
+pub async fn serve(addr: String, progress: watch::Receiver<CrawlProgress>, shutdown: watch::Receiver<bool>) {
+    let app = Router::new()
+        .route("/progress", get(current))
+        .route("/progress/events", get(events))
+        .with_state((progress, shutdown));
+
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => { error!("Failed to bind progress endpoint to {addr}: {e}"); return }
+    };
+    info!("Serving crawl progress on http://{addr}/progress");
+    if let Err(e) = axum::serve(listener, app).await { error!("Progress endpoint failed: {e}") }
+}
+
+/// The progress right now
+async fn current(State((progress, _)): State<(watch::Receiver<CrawlProgress>, watch::Receiver<bool>)>) -> Json<CrawlProgress> {
+    Json(progress.borrow().clone())
+}
+
+/// The progress now and each time it changes, until the crawl ends
+async fn events(State((progress, shutdown)): State<(watch::Receiver<CrawlProgress>, watch::Receiver<bool>)>) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    Sse::new(updates(progress, shutdown)).keep_alive(KeepAlive::default())
+}
+
+/// A stream of server-sent events with the progress, starting with how it is now
+///
+/// The stream ends when the crawl does, or once `shutdown` turns true so that it doesn't hold up
+/// a graceful shutdown.
+pub fn updates(progress: watch::Receiver<CrawlProgress>, shutdown: watch::Receiver<bool>) -> impl Stream<Item = Result<Event, axum::Error>> {
+    stream::unfold((progress, shutdown, true), |(mut progress, mut shutdown, first)| async move {
+        if *shutdown.borrow() { return None }
+        if !first {
+            tokio::select! {
+                changed = progress.changed() => changed.ok()?,
+                Ok(_) = shutdown.wait_for(|stop| *stop) => return None,
+            }
+        }
+        let snapshot = progress.borrow_and_update().clone();
+        Some((Event::default().event("progress").json_data(&snapshot), (progress, shutdown, false)))
+    })
+}
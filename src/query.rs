@@ -0,0 +1,99 @@
+use serde::Serialize;
+
+use crate::error::QueryError;
+use crate::lang;
+
+/// A search query split into the terms to match and the filters on them
+///
+/// Filters are written as `name:value` among the terms, so far just `lang:de` (or `lang:german`)
+/// to only search pages in a language.
+#[derive(Clone,Debug,Default,Serialize)]
+pub struct ParsedQuery {
+    /// The terms, in `websearch_to_tsquery` syntax
+    pub terms: String,
+    /// The language code pages have to be in
+    pub lang: Option<String>,
+}
+
+impl ParsedQuery {
+    /// Split the filters out of a query, failing if one isn't valid
+    pub fn parse(input: &str) -> Result<ParsedQuery, QueryError> {
+        let mut terms = Vec::new();
+        let mut lang = None;
+        for token in input.split_whitespace() {
+            match token.split_once(':') {
+                Some((name, value)) if name.eq_ignore_ascii_case("lang") => {
+                    let value = value.to_lowercase();
+                    let code = match lang::find(&value) {
+                        Some(language) => language.code().to_string(),
+                        None if lang::is_code(&value) => value,
+                        None => return Err(QueryError::UnknownLanguage { lang: value }),
+                    };
+                    lang = Some(code);
+                },
+                _ => terms.push(token),
+            }
+        }
+        Ok(ParsedQuery { terms: terms.join(" "), lang })
+    }
+
+    /// The query written out again with different terms, keeping the filters
+    pub fn with_terms(&self, terms: &str) -> String {
+        match &self.lang {
+            Some(lang) => format!("{terms} lang:{lang}"),
+            None => terms.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phrases_and_exclusions_are_kept_as_terms() {
+        let query = ParsedQuery::parse("  \"full text\"   search -mysql  ").unwrap();
+        assert_eq!(query.terms, "\"full text\" search -mysql");
+        assert_eq!(query.lang, None);
+    }
+
+    #[test]
+    fn lang_filters_are_split_out() {
+        let query = ParsedQuery::parse("Suchmaschine LANG:german crawler").unwrap();
+        assert_eq!(query.terms, "Suchmaschine crawler");
+        assert_eq!(query.lang.as_deref(), Some("de"));
+        assert_eq!(ParsedQuery::parse("lang:de-AT x").unwrap().lang.as_deref(), Some("de"));
+        assert_eq!(ParsedQuery::parse("lang:nb x").unwrap().lang.as_deref(), Some("no"));
+        // Languages without a configuration can still be filtered on
+        assert_eq!(ParsedQuery::parse("lang:ja x").unwrap().lang.as_deref(), Some("ja"));
+        // The last one wins
+        assert_eq!(ParsedQuery::parse("lang:de x lang:fr").unwrap().lang.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn unknown_languages_are_errors() {
+        assert!(matches!(ParsedQuery::parse("x lang:klingon"), Err(QueryError::UnknownLanguage { lang }) if lang == "klingon"));
+        assert!(matches!(ParsedQuery::parse("lang:"), Err(QueryError::UnknownLanguage { .. })));
+    }
+
+    #[test]
+    fn other_filters_are_left_as_terms() {
+        let query = ParsedQuery::parse("site:example.com rust http://example.com/").unwrap();
+        assert_eq!(query.terms, "site:example.com rust http://example.com/");
+        assert_eq!(query.lang, None);
+    }
+
+    #[test]
+    fn empty_queries_have_no_terms() {
+        for input in ["", "   ", "\t\n", "lang:en"] {
+            assert_eq!(ParsedQuery::parse(input).unwrap().terms, "", "{input:?}");
+        }
+    }
+
+    #[test]
+    fn corrected_terms_keep_the_filters() {
+        let query = ParsedQuery::parse("serch lang:fr").unwrap();
+        assert_eq!(query.with_terms("search"), "search lang:fr");
+        assert_eq!(ParsedQuery::parse("serch").unwrap().with_terms("search"), "search");
+    }
+}
@@ -0,0 +1,198 @@
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use std::fmt;
+
+use crate::error::RuleError;
+
+/// What a rule does with the URLs it matches
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleAction::Allow => write!(f, "allow"),
+            RuleAction::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// The pattern a rule matches the full URL against
+#[derive(Clone,Debug)]
+enum Pattern {
+    Regex(Regex),
+    Glob(GlobMatcher),
+}
+
+/// UrlRule - a single allow or deny rule
+///
+/// Rules are written as `<allow|deny>:<pattern>`, where the pattern is a glob (`*` matches
+/// anything, including `/`) or, prefixed with `re:`, a regex. For example
+/// `deny:*.pdf` or `allow:re:^https?://([a-z]+\.)?example\.com/`.
+#[derive(Clone,Debug)]
+pub struct UrlRule {
+    pub action: RuleAction,
+    pattern: Pattern,
+    /// The rule as it was written
+    spec: String,
+}
+
+impl UrlRule {
+    /// Parse a rule from its written form
+    pub fn parse(spec: &str) -> Result<UrlRule, RuleError> {
+        let err = |reason: String| RuleError { rule: spec.to_string(), reason };
+
+        let (action, pattern) = match spec.split_once(':') {
+            Some(("allow", pattern)) => (RuleAction::Allow, pattern),
+            Some(("deny", pattern)) => (RuleAction::Deny, pattern),
+            _ => return Err(err(String::from("rules must start with 'allow:' or 'deny:'"))),
+        };
+        if pattern.is_empty() { return Err(err(String::from("the pattern is empty"))) }
+
+        let pattern = match pattern.strip_prefix("re:") {
+            Some(re) => Pattern::Regex(Regex::new(re).map_err(|e| err(e.to_string()))?),
+            None => {
+                let glob = pattern.strip_prefix("glob:").unwrap_or(pattern);
+                Pattern::Glob(Glob::new(glob).map_err(|e| err(e.to_string()))?.compile_matcher())
+            }
+        };
+
+        Ok(UrlRule { action, pattern, spec: spec.to_string() })
+    }
+
+    /// Whether the rule's pattern matches a URL
+    pub fn matches(&self, url: &str) -> bool {
+        match &self.pattern {
+            Pattern::Regex(re) => re.is_match(url),
+            Pattern::Glob(glob) => glob.is_match(url),
+        }
+    }
+}
+
+impl fmt::Display for UrlRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+/// The decision for a URL, and the rule that made it
+#[derive(Debug)]
+pub enum Verdict<'a> {
+    /// A rule matched, at this (0-based) position in the list
+    Rule { index: usize, rule: &'a UrlRule },
+    /// No rule matched
+    Default(RuleAction),
+}
+
+impl Verdict<'_> {
+    /// Whether the URL may be crawled
+    pub fn allowed(&self) -> bool {
+        match self {
+            Verdict::Rule { rule, .. } => rule.action == RuleAction::Allow,
+            Verdict::Default(action) => *action == RuleAction::Allow,
+        }
+    }
+}
+
+/// UrlRules - an ordered list of allow and deny rules, where the first rule to match a URL wins
+///
+/// URLs no rule matches are allowed, unless there are any allow rules, in which case only what
+/// they match is crawled.
+#[derive(Clone,Debug,Default)]
+pub struct UrlRules {
+    rules: Vec<UrlRule>,
+}
+
+impl UrlRules {
+    /// Parse a list of rules, in order
+    pub fn parse<S: AsRef<str>>(specs: &[S]) -> Result<UrlRules, RuleError> {
+        let rules = specs.iter().map(|s| UrlRule::parse(s.as_ref())).collect::<Result<_, _>>()?;
+        Ok(UrlRules { rules })
+    }
+
+    /// Decide whether a URL may be crawled
+    pub fn check(&self, url: &str) -> Verdict<'_> {
+        match self.rules.iter().enumerate().find(|(_, rule)| rule.matches(url)) {
+            Some((index, rule)) => Verdict::Rule { index, rule },
+            None => Verdict::Default(self.default_action()),
+        }
+    }
+
+    /// What happens to URLs no rule matches
+    pub fn default_action(&self) -> RuleAction {
+        if self.rules.iter().any(|r| r.action == RuleAction::Allow) { RuleAction::Deny } else { RuleAction::Allow }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(specs: &[&str]) -> UrlRules {
+        UrlRules::parse(specs).unwrap()
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let rules = parse(&["allow:*/docs/*.pdf", "deny:*.pdf", "allow:*"]);
+        assert!(rules.check("https://example.com/docs/guide.pdf").allowed());
+        assert!(!rules.check("https://example.com/files/guide.pdf").allowed());
+        assert!(rules.check("https://example.com/").allowed());
+
+        let reversed = parse(&["deny:*.pdf", "allow:*/docs/*.pdf"]);
+        assert!(!reversed.check("https://example.com/docs/guide.pdf").allowed());
+    }
+
+    #[test]
+    fn verdict_names_the_rule() {
+        let rules = parse(&["deny:*.pdf", "deny:re:/private/"]);
+        match rules.check("https://example.com/private/page") {
+            Verdict::Rule { index, rule } => {
+                assert_eq!(index, 1);
+                assert_eq!(rule.to_string(), "deny:re:/private/");
+            },
+            verdict => panic!("expected a rule to match, got {verdict:?}"),
+        }
+    }
+
+    #[test]
+    fn glob_and_regex_patterns() {
+        let glob = UrlRule::parse("deny:*.pdf").unwrap();
+        assert!(glob.matches("https://example.com/a/b.pdf"));
+        assert!(!glob.matches("https://example.com/a/b.html"));
+
+        let explicit_glob = UrlRule::parse("deny:glob:https://example.com/*").unwrap();
+        assert!(explicit_glob.matches("https://example.com/a/b"));
+
+        let regex = UrlRule::parse(r"allow:re:^https?://([a-z]+\.)?example\.com/").unwrap();
+        assert_eq!(regex.action, RuleAction::Allow);
+        assert!(regex.matches("https://docs.example.com/"));
+        assert!(!regex.matches("https://example.org/"));
+    }
+
+    #[test]
+    fn default_action() {
+        assert_eq!(UrlRules::default().default_action(), RuleAction::Allow);
+        assert!(UrlRules::default().check("https://example.com/").allowed());
+
+        let deny_only = parse(&["deny:*.pdf"]);
+        assert!(matches!(deny_only.check("https://example.com/"), Verdict::Default(RuleAction::Allow)));
+
+        let with_allow = parse(&["allow:https://example.com/*"]);
+        assert!(matches!(with_allow.check("https://example.org/"), Verdict::Default(RuleAction::Deny)));
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for spec in ["*.pdf", "block:*.pdf", "Allow:*", "deny:", "allow:re:(", "deny:glob:[a"] {
+            let err = UrlRule::parse(spec).unwrap_err();
+            assert_eq!(err.rule, spec);
+        }
+        let err = UrlRules::parse(&["allow:*", "nope"]).unwrap_err();
+        assert_eq!(err.rule, "nope");
+        assert!(err.reason.contains("allow:"));
+    }
+}
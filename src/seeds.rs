@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+use crate::config::CrawlerConfig;
+use crate::error::SeedError;
+
+/// Seed - a URL to start crawling from
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Seed {
+    /// The URL to start from
+    pub url: String,
+    /// The max amount of links to follow from this seed, overriding the crawl's `max_hops`
+    pub max_hops: Option<u32>,
+    /// Seeds with a higher priority are crawled first (default 0)
+    pub priority: i32,
+}
+
+impl Seed {
+    /// Parse a seed from a URL, optionally followed by `hops=<n>` and `priority=<n>`, like
+    /// `example.com hops=2 priority=10`
+    ///
+    /// URLs without a scheme are taken to be `http://`.
+    pub fn parse(line: &str) -> Result<Seed, String> {
+        let mut parts = line.split_whitespace();
+        let url = parts.next().ok_or_else(|| String::from("no URL given"))?;
+        let url = if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_string()
+        } else {
+            format!("http://{url}")
+        };
+        let url = Url::parse(&url).map_err(|e| format!("invalid URL {url:?}: {e}"))?;
+        if url.host_str().is_none() { return Err(format!("{url} has no host")) }
+
+        let mut seed = Seed { url: url.to_string(), max_hops: None, priority: 0 };
+        for option in parts {
+            match option.split_once('=') {
+                Some(("hops", n)) => seed.max_hops = Some(n.parse().map_err(|_| format!("invalid hops {n:?}"))?),
+                Some(("priority", n)) => seed.priority = n.parse().map_err(|_| format!("invalid priority {n:?}"))?,
+                _ => return Err(format!("unknown option {option:?}, expected hops=<n> or priority=<n>")),
+            }
+        }
+        Ok(seed)
+    }
+
+    /// Parse a list of seeds, such as from the config file or command line
+    pub fn parse_all<S: AsRef<str>>(lines: &[S], origin: &str) -> Result<Vec<Seed>, SeedError> {
+        lines.iter().enumerate()
+            .map(|(n, line)| Seed::parse(line.as_ref())
+                .map_err(|reason| SeedError { origin: origin.to_string(), line: n + 1, reason }))
+            .collect()
+    }
+
+    /// The seeds from a crawler config, from both `seeds` and `seed_files`
+    pub fn from_config(config: &CrawlerConfig) -> Result<Vec<Seed>, SeedError> {
+        let mut seeds = Seed::parse_all(&config.seeds, "seeds")?;
+        for path in &config.seed_files {
+            seeds.extend(Seed::read_file(path)?);
+        }
+        Ok(seeds)
+    }
+
+    /// Read seeds from a file, one per line in the same format as `Seed::parse`
+    ///
+    /// Blank lines, and comments starting with a `#` at the start of a line or after a space, are
+    /// ignored.
+    pub fn read_file(path: &Path) -> Result<Vec<Seed>, SeedError> {
+        let origin = path.display().to_string();
+        let text = fs::read_to_string(path)
+            .map_err(|e| SeedError { origin: origin.clone(), line: 0, reason: e.to_string() })?;
+
+        let mut seeds = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = if line.trim_start().starts_with('#') { "" } else { line.split(" #").next().unwrap_or_default().trim() };
+            if line.is_empty() { continue }
+            seeds.push(Seed::parse(line)
+                .map_err(|reason| SeedError { origin: origin.clone(), line: n + 1, reason })?);
+        }
+        Ok(seeds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn parses_options() {
+        let seed = Seed::parse("example.com/docs hops=2 priority=-3").unwrap();
+        assert_eq!(seed, Seed { url: String::from("http://example.com/docs"), max_hops: Some(2), priority: -3 });
+
+        let seed = Seed::parse("  https://example.com  ").unwrap();
+        assert_eq!(seed, Seed { url: String::from("https://example.com/"), max_hops: None, priority: 0 });
+    }
+
+    #[test]
+    fn rejects_bad_seeds() {
+        assert!(Seed::parse("").is_err());
+        assert!(Seed::parse("example.com hops=-1").unwrap_err().contains("hops"));
+        assert!(Seed::parse("example.com priority=high").unwrap_err().contains("priority"));
+        assert!(Seed::parse("example.com depth=2").unwrap_err().contains("unknown option"));
+        assert!(Seed::parse("http://").is_err());
+    }
+
+    #[test]
+    fn parse_all_reports_line() {
+        let err = Seed::parse_all(&["example.com", "example.org hops=x"], "seeds").unwrap_err();
+        assert_eq!((err.origin.as_str(), err.line), ("seeds", 2));
+        assert_eq!(Seed::parse_all(&["a.example", "b.example"], "seeds").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reads_file_skipping_comments_and_blank_lines() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# seeds for the test\n\nexample.com hops=1  # the home page\n   # indented comment\nexample.org/#fragment priority=5").unwrap();
+
+        let seeds = Seed::read_file(file.path()).unwrap();
+        assert_eq!(seeds, vec![
+            Seed { url: String::from("http://example.com/"), max_hops: Some(1), priority: 0 },
+            Seed { url: String::from("http://example.org/#fragment"), max_hops: None, priority: 5 },
+        ]);
+    }
+
+    #[test]
+    fn file_errors_report_line() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "example.com\n# comment\n\nexample.org hops=many").unwrap();
+
+        let err = Seed::read_file(file.path()).unwrap_err();
+        assert_eq!(err.line, 4);
+        assert!(err.reason.contains("hops"));
+
+        let err = Seed::read_file(Path::new("/nonexistent/seeds.txt")).unwrap_err();
+        assert_eq!(err.line, 0);
+    }
+}
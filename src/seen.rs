@@ -0,0 +1,171 @@
+use memmap2::MmapMut;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// SeenSet - every URL a crawl has queued, so none is queued twice
+///
+/// By default this is an in-memory hash set. For crawls too big to hold every URL in memory a
+/// Bloom filter backed by a file can be used instead, at the cost of occasionally skipping a URL
+/// that wasn't really seen.
+#[derive(Debug)]
+pub enum SeenSet {
+    Memory(HashSet<String>),
+    Bloom(BloomFilter),
+}
+
+impl Default for SeenSet {
+    fn default() -> SeenSet {
+        SeenSet::Memory(HashSet::new())
+    }
+}
+
+impl SeenSet {
+    /// Add a URL, returning false if it has (probably, for a Bloom filter) been seen before
+    pub fn insert(&mut self, url: &str) -> bool {
+        match self {
+            SeenSet::Memory(set) => if set.contains(url) { false } else { set.insert(url.to_string()) },
+            SeenSet::Bloom(bloom) => bloom.insert(url),
+        }
+    }
+
+    /// The amount of URLs seen
+    pub fn len(&self) -> usize {
+        match self {
+            SeenSet::Memory(set) => set.len(),
+            SeenSet::Bloom(bloom) => bloom.items,
+        }
+    }
+}
+
+/// Where and how big to make a crawl's Bloom filter
+#[derive(Clone,Debug)]
+pub struct BloomSettings {
+    /// The file holding the filter, overwritten at the start of each crawl
+    pub path: PathBuf,
+    /// The amount of URLs the filter is sized for
+    pub capacity: usize,
+    /// The chance of a URL wrongly being taken as seen, at capacity
+    pub error_rate: f64,
+}
+
+/// BloomFilter - a Bloom filter whose bits live in a memory-mapped file
+///
+/// The filter is sized for an expected amount of items and false positive rate. Going over that
+/// amount still works, but false positives become more and more likely.
+#[derive(Debug)]
+pub struct BloomFilter {
+    map: MmapMut,
+    /// The amount of bits in the filter
+    bits: u64,
+    /// The amount of bits set per item
+    hashes: u32,
+    /// The amount of items added
+    items: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter for `capacity` items at a false positive rate of `error_rate`,
+    /// overwriting the file at `path`
+    pub fn create(path: &Path, capacity: usize, error_rate: f64) -> io::Result<BloomFilter> {
+        let capacity = capacity.max(1) as f64;
+        let error_rate = error_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let bits = (-capacity * error_rate.ln() / std::f64::consts::LN_2.powi(2)).ceil().max(64.0) as u64;
+        let hashes = ((bits as f64 / capacity) * std::f64::consts::LN_2).round().max(1.0) as u32;
+
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(bits.div_ceil(8))?;
+        // SAFETY: the file was just created and truncated for this filter, and nothing else is
+        // expected to change it while it's mapped
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        Ok(BloomFilter { map, bits, hashes, items: 0 })
+    }
+
+    /// Add an item, returning false if it was (probably) already there
+    pub fn insert(&mut self, item: &str) -> bool {
+        let (h1, h2) = Self::hash_pair(item);
+        let mut new = false;
+        for i in 0..u64::from(self.hashes) {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.bits;
+            let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+            if self.map[byte] & mask == 0 {
+                self.map[byte] |= mask;
+                new = true;
+            }
+        }
+        if new { self.items += 1 }
+        new
+    }
+
+    /// Two independent hashes of an item, combined to pick its bits (double hashing)
+    fn hash_pair(item: &str) -> (u64, u64) {
+        let mut first = DefaultHasher::new();
+        item.hash(&mut first);
+        let mut second = DefaultHasher::new();
+        (item, 0x9e37_79b9_7f4a_7c15u64).hash(&mut second);
+        (first.finish(), second.finish() | 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sized_from_capacity_and_error_rate() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let bloom = BloomFilter::create(file.path(), 1000, 0.01).unwrap();
+        assert_eq!((bloom.bits, bloom.hashes), (9586, 7));
+        assert_eq!(std::fs::metadata(file.path()).unwrap().len(), 1199);
+
+        let tiny = BloomFilter::create(file.path(), 0, 0.9).unwrap();
+        assert_eq!((tiny.bits, tiny.hashes), (64, 44));
+    }
+
+    #[test]
+    fn insert_finds_seen_items() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut bloom = BloomFilter::create(file.path(), 1000, 0.01).unwrap();
+        // A few new items may already look seen, as false positives
+        let added = (0..1000).filter(|n| bloom.insert(&format!("https://example.com/{n}"))).count();
+        assert!(added > 980, "only {added} added");
+        assert_eq!(bloom.items, added);
+        for n in 0..1000 {
+            assert!(!bloom.insert(&format!("https://example.com/{n}")));
+        }
+
+        // Probing inserts too, so only a few are tried to stay near capacity
+        let false_positives = (0..200).filter(|n| !bloom.insert(&format!("https://example.org/{n}"))).count();
+        assert!(false_positives <= 10, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn existing_file_is_overwritten() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), vec![0xff; 4096]).unwrap();
+
+        let mut bloom = BloomFilter::create(file.path(), 100, 0.01).unwrap();
+        assert!(bloom.insert("https://example.com/"));
+        assert!(std::fs::metadata(file.path()).unwrap().len() < 4096);
+        drop(bloom);
+
+        let mut reopened = BloomFilter::create(file.path(), 100, 0.01).unwrap();
+        assert!(reopened.insert("https://example.com/"));
+    }
+
+    #[test]
+    fn seen_sets() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let bloom = BloomFilter::create(file.path(), 100, 0.01).unwrap();
+        for mut seen in [SeenSet::default(), SeenSet::Bloom(bloom)] {
+            assert!(seen.insert("https://example.com/"));
+            assert!(!seen.insert("https://example.com/"));
+            assert!(seen.insert("https://example.com/about"));
+            assert_eq!(seen.len(), 2);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use tokio::sync::watch;
+use log::{info, warn};
+
+/// Wait for SIGINT (Ctrl-C) or, on unix, SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await { warn!("Couldn't listen for Ctrl-C: {e}") }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => { signal.recv().await; },
+            Err(e) => { warn!("Couldn't listen for SIGTERM: {e}"); std::future::pending::<()>().await }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Listen for shutdown signals, returning a receiver that turns true once one arrives
+///
+/// A second signal exits straight away, without waiting for anything to finish.
+pub fn listen() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        signal().await;
+        info!("Shutting down, signal again to exit now");
+        sender.send_replace(true);
+        signal().await;
+        warn!("Exiting without finishing up");
+        std::process::exit(130);
+    });
+    receiver
+}
+
+/// Wait until shutdown has been asked for on a receiver from `listen`
+pub async fn requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use url::Url;
+
+use crate::config::CrawlerConfig;
+
+/// TrapLimits - the thresholds past which URLs look like a crawl trap, where `None` is no limit
+#[derive(Clone,Debug)]
+pub struct TrapLimits {
+    /// The longest URL crawled
+    pub max_url_length: Option<usize>,
+    /// The most times one path segment can appear in a URL
+    pub max_segment_repeats: Option<usize>,
+    /// The most URLs queued with the same path pattern (see `TrapDetector::pattern`)
+    pub max_pattern_urls: Option<usize>,
+    /// The most URLs queued from any one host
+    pub max_host_urls: Option<usize>,
+}
+
+impl Default for TrapLimits {
+    fn default() -> TrapLimits {
+        TrapLimits {
+            max_url_length: Some(2048),
+            max_segment_repeats: Some(3),
+            max_pattern_urls: Some(1000),
+            max_host_urls: Some(50_000),
+        }
+    }
+}
+
+impl TrapLimits {
+    /// The limits from a crawler config, where 0 is no limit
+    pub fn from_config(config: &CrawlerConfig) -> TrapLimits {
+        let limit = |n: usize| Some(n).filter(|n| *n > 0);
+        TrapLimits {
+            max_url_length: limit(config.trap_max_url_length),
+            max_segment_repeats: limit(config.trap_max_segment_repeats),
+            max_pattern_urls: limit(config.trap_max_pattern_urls),
+            max_host_urls: limit(config.trap_max_host_urls),
+        }
+    }
+}
+
+/// Why a URL was taken to be a trap
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Trap {
+    /// The URL is longer than the limit
+    UrlLength { length: usize, max: usize },
+    /// A path segment is repeated, like `/a/b/a/b/a/b/`
+    RepeatedSegment { segment: String, count: usize },
+    /// Too many URLs have been queued with the same path pattern, like calendars or facets
+    Pattern { pattern: String, max: usize },
+    /// Too many URLs have been queued from the host
+    HostUrls { host: String, max: usize },
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::UrlLength { length, max } => write!(f, "URL is {length} characters, over the limit of {max}"),
+            Trap::RepeatedSegment { segment, count } => write!(f, "path segment {segment:?} is repeated {count} times"),
+            Trap::Pattern { pattern, max } => write!(f, "over {max} URLs match {pattern}"),
+            Trap::HostUrls { host, max } => write!(f, "over {max} URLs queued from {host}"),
+        }
+    }
+}
+
+/// TrapDetector - spots URLs likely to be crawl traps, like endless calendars, faceted navigation
+/// and session IDs, which never run out of new URLs to follow
+///
+/// URLs are counted as they're queued, by host and by path pattern. Once a pattern or host has
+/// had its share, any more URLs for it are quarantined, as are overly long URLs and ones with
+/// repeating paths.
+#[derive(Clone,Debug,Default)]
+pub struct TrapDetector {
+    limits: TrapLimits,
+    /// The URLs queued so far with each path pattern
+    patterns: HashMap<String, usize>,
+    /// The URLs queued so far from each host
+    hosts: HashMap<String, usize>,
+    /// The URLs taken to be traps so far
+    quarantine: HashSet<String>,
+}
+
+impl TrapDetector {
+    /// Create a TrapDetector with some limits
+    pub fn new(limits: TrapLimits) -> TrapDetector {
+        TrapDetector { limits, ..Default::default() }
+    }
+
+    /// Check whether a URL looks like a trap, before it's queued
+    pub fn check(&self, url: &str, host: &str) -> Option<Trap> {
+        if let Some(max) = self.limits.max_url_length.filter(|max| url.len() > *max) {
+            return Some(Trap::UrlLength { length: url.len(), max });
+        }
+        let parsed = Url::parse(url).ok()?;
+        if let Some(max) = self.limits.max_segment_repeats {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for segment in parsed.path_segments().into_iter().flatten().filter(|s| !s.is_empty()) {
+                let count = counts.entry(segment).or_default();
+                *count += 1;
+                if *count > max {
+                    return Some(Trap::RepeatedSegment { segment: segment.to_string(), count: *count });
+                }
+            }
+        }
+        if let Some(max) = self.limits.max_pattern_urls {
+            let pattern = Self::pattern(&parsed);
+            if self.patterns.get(&pattern).is_some_and(|count| *count >= max) {
+                return Some(Trap::Pattern { pattern, max });
+            }
+        }
+        if let Some(max) = self.limits.max_host_urls {
+            if self.hosts.get(host).is_some_and(|count| *count >= max) {
+                return Some(Trap::HostUrls { host: host.to_string(), max });
+            }
+        }
+        None
+    }
+
+    /// Count a URL that has been queued towards its pattern and host
+    pub fn record(&mut self, url: &str, host: &str) {
+        if let Ok(parsed) = Url::parse(url) {
+            *self.patterns.entry(Self::pattern(&parsed)).or_default() += 1;
+        }
+        *self.hosts.entry(host.to_string()).or_default() += 1;
+    }
+
+    /// Quarantine a URL, returning false if it already was
+    pub fn quarantine(&mut self, url: &str) -> bool {
+        self.quarantine.insert(url.to_string())
+    }
+
+    /// The amount of URLs quarantined
+    pub fn quarantined(&self) -> usize {
+        self.quarantine.len()
+    }
+
+    /// The shape of a URL, used to group URLs that only differ by IDs, dates or parameter values
+    ///
+    /// Digits are replaced with `N`, path parameters (like `;jsessionid=...`) are dropped and only
+    /// the sorted names of query parameters are kept, so `/cal/2024/05?view=day&sid=1f` becomes
+    /// `host/cal/N/N?sid&view`.
+    pub fn pattern(url: &Url) -> String {
+        let mut pattern = url.host_str().unwrap_or_default().to_string();
+        for segment in url.path_segments().into_iter().flatten() {
+            let segment = segment.split(';').next().unwrap_or_default();
+            pattern.push('/');
+            let mut in_digits = false;
+            for c in segment.chars() {
+                if c.is_ascii_digit() {
+                    if !in_digits { pattern.push('N') }
+                    in_digits = true;
+                } else {
+                    pattern.push(c);
+                    in_digits = false;
+                }
+            }
+        }
+        let mut keys: Vec<_> = url.query_pairs().map(|(k, _)| k.into_owned()).collect();
+        if !keys.is_empty() {
+            keys.sort();
+            keys.dedup();
+            pattern.push('?');
+            pattern.push_str(&keys.join("&"));
+        }
+        pattern
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_limits() -> TrapLimits {
+        TrapLimits { max_url_length: None, max_segment_repeats: None, max_pattern_urls: None, max_host_urls: None }
+    }
+
+    /// Check and record a URL like the crawler does when queueing it
+    fn queue(detector: &mut TrapDetector, url: &str) -> Option<Trap> {
+        let host = Url::parse(url).unwrap().host_str().unwrap().to_string();
+        let trap = detector.check(url, &host);
+        if trap.is_none() { detector.record(url, &host) }
+        trap
+    }
+
+    #[test]
+    fn url_length() {
+        let detector = TrapDetector::new(TrapLimits { max_url_length: Some(30), ..no_limits() });
+        assert_eq!(detector.check("https://example.com/short", "example.com"), None);
+        let long = "https://example.com/a-rather-long-path";
+        assert_eq!(detector.check(long, "example.com"), Some(Trap::UrlLength { length: long.len(), max: 30 }));
+    }
+
+    #[test]
+    fn repeated_segments() {
+        let detector = TrapDetector::new(TrapLimits { max_segment_repeats: Some(2), ..no_limits() });
+        assert_eq!(detector.check("https://example.com/a/b/a/b/", "example.com"), None);
+        assert_eq!(detector.check("https://example.com/a/b/a/b/a/b/", "example.com"),
+                   Some(Trap::RepeatedSegment { segment: String::from("a"), count: 3 }));
+    }
+
+    #[test]
+    fn pattern_urls() {
+        let mut detector = TrapDetector::new(TrapLimits { max_pattern_urls: Some(2), ..no_limits() });
+        assert_eq!(queue(&mut detector, "https://example.com/cal/2024/05?view=day"), None);
+        assert_eq!(queue(&mut detector, "https://example.com/cal/2024/06?view=week"), None);
+        assert_eq!(queue(&mut detector, "https://example.com/cal/2024/07?view=day"),
+                   Some(Trap::Pattern { pattern: String::from("example.com/cal/N/N?view"), max: 2 }));
+        // Other patterns on the host still get their share
+        assert_eq!(queue(&mut detector, "https://example.com/about"), None);
+    }
+
+    #[test]
+    fn host_urls() {
+        let mut detector = TrapDetector::new(TrapLimits { max_host_urls: Some(2), ..no_limits() });
+        assert_eq!(queue(&mut detector, "https://example.com/a"), None);
+        assert_eq!(queue(&mut detector, "https://example.com/b"), None);
+        assert_eq!(queue(&mut detector, "https://example.com/c"),
+                   Some(Trap::HostUrls { host: String::from("example.com"), max: 2 }));
+        assert_eq!(queue(&mut detector, "https://example.org/c"), None);
+    }
+
+    #[test]
+    fn zero_is_no_limit() {
+        let config = CrawlerConfig {
+            trap_max_url_length: 0,
+            trap_max_segment_repeats: 0,
+            trap_max_pattern_urls: 0,
+            trap_max_host_urls: 0,
+            ..Default::default()
+        };
+        let limits = TrapLimits::from_config(&config);
+        assert!(limits.max_url_length.is_none() && limits.max_segment_repeats.is_none());
+        assert!(limits.max_pattern_urls.is_none() && limits.max_host_urls.is_none());
+
+        let mut detector = TrapDetector::new(limits);
+        let long = format!("https://example.com/{}", "a/".repeat(2000));
+        assert_eq!(queue(&mut detector, &long), None);
+        for n in 0..100 {
+            assert_eq!(queue(&mut detector, &format!("https://example.com/page/{n}")), None);
+        }
+
+        let defaults = TrapLimits::from_config(&CrawlerConfig::default());
+        assert_eq!(defaults.max_url_length, Some(2048));
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern = |url: &str| TrapDetector::pattern(&Url::parse(url).unwrap());
+        assert_eq!(pattern("https://example.com/cal/2024/05?view=day&sid=1f&view=week"), "example.com/cal/N/N?sid&view");
+        assert_eq!(pattern("https://example.com/shop;jsessionid=abc/item42"), "example.com/shop/itemN");
+    }
+
+    #[test]
+    fn quarantine_counts_urls_once() {
+        let mut detector = TrapDetector::default();
+        assert!(detector.quarantine("https://example.com/trap"));
+        assert!(!detector.quarantine("https://example.com/trap"));
+        assert_eq!(detector.quarantined(), 1);
+    }
+}